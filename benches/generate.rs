@@ -0,0 +1,48 @@
+// The cost of generating a single code. allocating reproduces how codes were generated before
+// secrets were decoded on the stack, string is the String returning API and code is the API that
+// doesn't allocate.
+#![allow(clippy::needless_return, clippy::unnecessary_to_owned)]
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use lotp::hotp::{self, Digits};
+use ring::hmac;
+
+// Generate a code the way it was before, decoding the secret into a vector and prepending zeroes
+// one at a time.
+fn allocating(counter: &u64, secret: &str) -> String {
+    let bytes = base32::decode(base32::Alphabet::RFC4648 { padding: false }, secret).unwrap();
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &bytes);
+    let tag = hmac::sign(&key, &counter.to_be_bytes().to_vec());
+    let offset = (tag.as_ref()[tag.as_ref().len() - 1] & 0xf) as usize;
+    let bin_code = (tag.as_ref()[offset] as u32 & 0x7f) << 24
+        | (tag.as_ref()[offset + 1] as u32) << 16
+        | (tag.as_ref()[offset + 2] as u32) << 8
+        | (tag.as_ref()[offset + 3] as u32);
+
+    let mut string = format!("{}", bin_code % 1_000_000);
+    while string.len() < 6 {
+        string.insert(0, '0');
+    }
+
+    return string;
+}
+
+fn generate(c: &mut Criterion) {
+    let secret = String::from("abcdef234567abcdef234567abcdef23");
+    let mut group = c.benchmark_group("generate");
+
+    group.bench_function("allocating", |b| {
+        b.iter(|| allocating(black_box(&400), black_box(&secret)))
+    });
+    group.bench_function("string", |b| {
+        b.iter(|| hotp::generate_6_digit_hotp_string(black_box(&400), black_box(&secret)))
+    });
+    group.bench_function("code", |b| {
+        b.iter(|| hotp::generate_hotp_code(black_box(&400), black_box(&secret), &Digits::Six))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, generate);
+criterion_main!(benches);
@@ -0,0 +1,102 @@
+// Throughput of SharedVerifier as the amount of threads verifying codes grows. Every thread
+// verifies codes for its own set of credentials, so each call computes the full window and takes
+// its shard's lock. wrong submits codes that never match, accepted submits a fresh valid code on
+// every call, so each call also updates the replay state that the shards protect.
+#![allow(clippy::needless_return)]
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use lotp::otp::{OtpGenerator, Totp};
+use lotp::shared::SharedVerifier;
+use lotp::throttle::FixedLockout;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CALLS_PER_THREAD: u64 = 2_000;
+const CREDENTIALS_PER_THREAD: u64 = 64;
+
+fn verifier() -> SharedVerifier<FixedLockout> {
+    return SharedVerifier::new(FixedLockout {
+        max_failures: 0,
+        lockout_secs: 0,
+    });
+}
+
+// Time every thread making CALLS_PER_THREAD calls to verify against a new verifier.
+// verify - Makes a single call, given the verifier, the thread's credential IDs and the call.
+fn run<F>(threads: &u64, iterations: &u64, verify: F) -> Duration
+where
+    F: Fn(&SharedVerifier<FixedLockout>, &[String], &u64) + Sync,
+{
+    let ids: Vec<Vec<String>> = (0..*threads)
+        .map(|t| {
+            (0..CREDENTIALS_PER_THREAD)
+                .map(|i| format!("user-{}-{}", t, i))
+                .collect()
+        })
+        .collect();
+    let mut elapsed = Duration::ZERO;
+
+    for _ in 0..*iterations {
+        // A new verifier each time, so accepted codes aren't replays of an earlier iteration.
+        let verifier = verifier();
+        let start = Instant::now();
+        thread::scope(|s| {
+            for ids in ids.iter() {
+                let (verifier, verify) = (&verifier, &verify);
+                s.spawn(move || {
+                    for i in 0..CALLS_PER_THREAD {
+                        verify(verifier, ids, &i);
+                    }
+                });
+            }
+        });
+        elapsed += start.elapsed();
+    }
+
+    return elapsed;
+}
+
+fn shared_verifier(c: &mut Criterion) {
+    let totp = Totp::new(&String::from("abcdef234567abcdef234567"));
+    let wrong = String::from("000000");
+
+    // Each credential is used once per time step, so every call accepts a new code.
+    let steps = CALLS_PER_THREAD / CREDENTIALS_PER_THREAD + 1;
+    let codes: Vec<String> = (0..steps)
+        .map(|s| totp.generate_at(&(s * totp.period)).unwrap())
+        .collect();
+
+    let mut group = c.benchmark_group("shared_verifier");
+
+    for threads in [1u64, 2, 4, 8] {
+        group.throughput(Throughput::Elements(threads * CALLS_PER_THREAD));
+        group.bench_with_input(BenchmarkId::new("wrong", threads), &threads, |b, t| {
+            b.iter_custom(|iterations| {
+                return run(t, &iterations, |verifier, ids, i| {
+                    let id = &ids[(*i % CREDENTIALS_PER_THREAD) as usize];
+                    let _ = verifier.verify_totp_at(id, &totp, &wrong, &1000);
+                });
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("accepted", threads), &threads, |b, t| {
+            b.iter_custom(|iterations| {
+                return run(t, &iterations, |verifier, ids, i| {
+                    let id = &ids[(*i % CREDENTIALS_PER_THREAD) as usize];
+                    let step = *i / CREDENTIALS_PER_THREAD;
+                    let outcome = verifier.verify_totp_at(
+                        id,
+                        &totp,
+                        &codes[step as usize],
+                        &(step * totp.period),
+                    );
+                    debug_assert!(outcome.unwrap().is_valid());
+                });
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, shared_verifier);
+criterion_main!(benches);
@@ -9,7 +9,7 @@ fn main() {
         // Generate a 6 digit HOTP code, from the secret and the counter.
         match hotp::generate_6_digit_hotp_string(&i, &secret) {
             Ok(s) => println!("{}", s), // Successfully generated the code print it.
-            Err(e) => println!("An error occurred: {}", e.description()), // An error occurred when generating the code, the ErrorType enum contains the possible error types.
+            Err(e) => println!("An error occurred: {}", e), // An error occurred when generating the code, the Error enum contains the possible errors.
         }
     }
 }
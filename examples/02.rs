@@ -11,7 +11,7 @@ fn main() {
         // Generate a 6 digit TOTP code from the secret, for every 10 seconds.
         match totp::generate_6_digit_totp_string(&secret, &10) {
             Ok(s) => println!("{} ({})", s, i), // Successfully generated the code print it.
-            Err(e) => println!("An error occurred: {}", e.description()), // An error occurred when generating the code, the ErrorType enum contains the possible error types.
+            Err(e) => println!("An error occurred: {}", e), // An error occurred when generating the code, the Error enum contains the possible errors.
         }
 
         thread::sleep(time::Duration::from_secs(1));
@@ -0,0 +1,422 @@
+// Audit events for every verification attempt. Events describe the credential, the outcome and
+// the credential's lockout state, they never contain the submitted code or any key material. The
+// verification functions take an Auditor, or an observer in the case of SharedVerifier, and report
+// every attempt to it, whatever the outcome.
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use crate::otp::OtpVerifier;
+use crate::outcome::Outcome;
+use crate::throttle::AttemptState;
+use alloc::string::String;
+use core::fmt;
+
+// A single verification attempt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AuditEvent<'a> {
+    pub credential_id: &'a str, // The identifier of the credential that was checked.
+    pub time: Option<u64>,      // The unix time of the attempt in seconds, None for HOTP checks.
+    pub result: &'a Result<Outcome, Error>, // The result of the verification.
+    pub attempts: Option<AttemptState>, // The failed attempts after this one, if they are kept.
+}
+
+impl AuditEvent<'_> {
+    // Returns the outcome, or None if the verification failed with an error.
+    pub fn outcome(&self) -> Option<Outcome> {
+        return self.result.as_ref().ok().copied();
+    }
+
+    // Returns the matched counter minus the expected one if the code was accepted.
+    pub fn step_delta(&self) -> Option<i64> {
+        match self.result {
+            Ok(Outcome::Valid { step_delta, .. }) => return Some(*step_delta),
+            _ => return None,
+        }
+    }
+
+    // Returns true if the code had already been accepted.
+    pub fn replayed(&self) -> bool {
+        return matches!(self.result, Ok(Outcome::Replayed));
+    }
+
+    // Returns the unix time the credential is locked out until, either because the attempt was
+    // refused or because it was a failure that locked the credential.
+    pub fn locked_until(&self) -> Option<u64> {
+        match self.result {
+            Ok(Outcome::LockedOut { until }) => return Some(*until),
+            _ => {
+                return self
+                    .attempts
+                    .and_then(|a| a.locked_until(&self.time.unwrap_or(0)))
+            }
+        }
+    }
+
+    // Returns the amount of consecutive failed attempts if they are kept.
+    pub fn failures(&self) -> Option<u32> {
+        return self.attempts.map(|a| a.failures());
+    }
+}
+
+// Receives an event for every verification attempt, such as to write a compliance log.
+pub trait AuditObserver {
+    // Called once for each attempt after it has been verified.
+    // event - The attempt, it never contains the submitted code.
+    fn on_attempt(&self, event: &AuditEvent);
+}
+
+// The observer that a verification reports its attempt to, and the credential reported in the
+// event.
+#[derive(Clone, Copy)]
+pub struct Auditor<'a> {
+    pub credential_id: &'a str,          // The identifier reported in events.
+    pub observer: &'a dyn AuditObserver, // The observer that receives the events.
+}
+
+impl<'a> Auditor<'a> {
+    // Creates an auditor.
+    // credential_id - The identifier reported in events, it shouldn't contain secrets.
+    // observer - The observer that receives the events.
+    pub fn new(credential_id: &'a str, observer: &'a dyn AuditObserver) -> Auditor<'a> {
+        return Auditor {
+            credential_id,
+            observer,
+        };
+    }
+}
+
+impl fmt::Debug for Auditor<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("Auditor")
+            .field("credential_id", &self.credential_id)
+            .finish_non_exhaustive();
+    }
+}
+
+// Reports an attempt to the auditor, if there is one, and returns its result.
+// time - The unix time of the attempt, None for HOTP checks.
+// attempts - The failed attempts after this one, if the verifier keeps them.
+pub(crate) fn report(
+    auditor: Option<&Auditor>,
+    time: Option<u64>,
+    result: Result<Outcome, Error>,
+    attempts: Option<AttemptState>,
+) -> Result<Outcome, Error> {
+    if let Some(a) = auditor {
+        a.observer.on_attempt(&AuditEvent {
+            credential_id: a.credential_id,
+            time,
+            result: &result,
+            attempts,
+        });
+    }
+
+    return result;
+}
+
+// Wraps a verifier so that every attempt is reported to an observer.
+pub struct Audited<'a, V: OtpVerifier> {
+    pub credential_id: String,       // The identifier reported in events.
+    pub inner: V,                    // The verifier that checks the codes.
+    observer: &'a dyn AuditObserver, // The observer that receives the events.
+}
+
+impl<'a, V: OtpVerifier> Audited<'a, V> {
+    // Creates an audited verifier.
+    // credential_id - The identifier reported in events, it shouldn't contain secrets.
+    // inner - The verifier that checks the codes.
+    // observer - The observer that receives the events.
+    #[allow(clippy::ptr_arg)]
+    pub fn new(
+        credential_id: &String,
+        inner: V,
+        observer: &'a dyn AuditObserver,
+    ) -> Audited<'a, V> {
+        return Audited {
+            credential_id: credential_id.clone(),
+            inner,
+            observer,
+        };
+    }
+}
+
+impl<V: OtpVerifier> OtpVerifier for Audited<'_, V> {
+    fn verify_at(&mut self, comparison: &String, now: &u64) -> Result<Outcome, Error> {
+        let auditor = Auditor::new(&self.credential_id, self.observer);
+        return self.inner.verify_audited_at(comparison, now, &auditor);
+    }
+
+    fn attempts(&self) -> Option<AttemptState> {
+        return self.inner.attempts();
+    }
+}
+
+// An observer that emits every attempt as a tracing event with the target lotp::audit. Accepted
+// codes are logged at the info level and everything else at the warn level.
+#[cfg(feature = "tracing")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TracingObserver;
+
+#[cfg(feature = "tracing")]
+impl AuditObserver for TracingObserver {
+    fn on_attempt(&self, event: &AuditEvent) {
+        let outcome = match event.result {
+            Ok(Outcome::Valid { .. }) => "valid",
+            Ok(Outcome::Invalid) => "invalid",
+            Ok(Outcome::Replayed) => "replayed",
+            Ok(Outcome::LockedOut { .. }) => "locked_out",
+            Ok(Outcome::Malformed) => "malformed",
+            Err(_) => "error",
+        };
+        let error = event.result.as_ref().err().map(|e| e.to_string());
+
+        if matches!(event.result, Ok(Outcome::Valid { .. })) {
+            tracing::info!(
+                target: "lotp::audit",
+                credential_id = event.credential_id,
+                time = event.time,
+                outcome,
+                step_delta = event.step_delta(),
+                "otp verification attempt"
+            );
+        } else {
+            tracing::warn!(
+                target: "lotp::audit",
+                credential_id = event.credential_id,
+                time = event.time,
+                outcome,
+                replayed = event.replayed(),
+                failures = event.failures(),
+                locked_until = event.locked_until(),
+                error,
+                "otp verification attempt"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // The credential, outcome and lockout of an event.
+    type Recorded = (String, Option<Outcome>, Option<u64>);
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<Recorded>>,
+    }
+
+    impl AuditObserver for Recorder {
+        fn on_attempt(&self, event: &AuditEvent) {
+            self.events.lock().unwrap().push((
+                String::from(event.credential_id),
+                event.outcome(),
+                event.locked_until(),
+            ));
+        }
+    }
+
+    #[test]
+    fn test_audit_event() {
+        let result = Ok(Outcome::Valid {
+            counter: 33,
+            step_delta: -1,
+        });
+        let event = AuditEvent {
+            credential_id: "user-1/phone",
+            time: Some(1000),
+            result: &result,
+            attempts: Some(AttemptState::new()),
+        };
+        assert_eq!(event.step_delta(), Some(-1));
+        assert!(!event.replayed());
+
+        let result = Ok(Outcome::LockedOut { until: 1030 });
+        let event = AuditEvent {
+            result: &result,
+            ..event
+        };
+        assert_eq!(event.locked_until(), Some(1030));
+        assert_eq!(event.step_delta(), None);
+
+        // A failure that locks the credential is reported with the lockout.
+        let result = Ok(Outcome::Invalid);
+        let event = AuditEvent {
+            result: &result,
+            attempts: Some(AttemptState::from_parts(&3, &Some(1060))),
+            ..event
+        };
+        assert_eq!(event.locked_until(), Some(1060));
+        assert_eq!(event.failures(), Some(3));
+    }
+
+    #[test]
+    fn test_audited_verifier() {
+        use crate::otp::{OtpGenerator, Totp};
+        let recorder = Recorder::default();
+        let totp = Totp::new(&String::from("abcdef234567abcdef234567"));
+        let code = totp.generate_at(&1000).unwrap();
+        let mut audited = Audited::new(&String::from("user-1/phone"), totp, &recorder);
+
+        for _ in 0..2 {
+            match audited.verify_at(&code, &1000) {
+                Ok(_) => (),
+                Err(e) => panic!("An error occurred when verifying the totp: {}", e),
+            }
+        }
+
+        let events = recorder.events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].0, "user-1/phone");
+        assert!(events[0].1.unwrap().is_valid());
+        assert_eq!(events[1].1, Some(Outcome::Replayed));
+    }
+
+    #[test]
+    fn test_verifier_reports_lockout() {
+        use crate::hotp::{self, Digits};
+        use crate::throttle::FixedLockout;
+        use crate::verifier::Verifier;
+        let recorder = Recorder::default();
+        let auditor = Auditor::new("user-1/token", &recorder);
+        let policy = FixedLockout {
+            max_failures: 2,
+            lockout_secs: 60,
+        };
+        let secret = String::from("abcdef234567abcdef234567");
+        let code = hotp::generate_6_digit_hotp_string(&5, &secret).unwrap();
+        let wrong = if code == "000000" { "111111" } else { "000000" };
+        let mut verifier = Verifier::new();
+
+        for comparison in [wrong, wrong, code.as_str()] {
+            let _ = verifier.verify_hotp_throttled(
+                &policy,
+                &1000,
+                &5,
+                &secret,
+                &0,
+                &String::from(comparison),
+                &Digits::Six,
+                Some(&auditor),
+            );
+        }
+
+        // The second failure reports the lockout it triggered, the next attempt is refused.
+        let events = recorder.events.lock().unwrap();
+        assert_eq!(
+            *events,
+            vec![
+                (String::from("user-1/token"), Some(Outcome::Invalid), None),
+                (
+                    String::from("user-1/token"),
+                    Some(Outcome::Invalid),
+                    Some(1060)
+                ),
+                (
+                    String::from("user-1/token"),
+                    Some(Outcome::LockedOut { until: 1060 }),
+                    Some(1060)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_reports_attempt() {
+        use crate::hotp::Digits;
+        use crate::totp;
+        let recorder = Recorder::default();
+        let auditor = Auditor::new("user-1/phone", &recorder);
+
+        let result = totp::check_totp_at(
+            &String::from("abcdef234567abcdef234567"),
+            &1000,
+            &1,
+            &String::from("12345"),
+            &30,
+            &Digits::Six,
+            Some(&auditor),
+        );
+        assert_eq!(result, Ok(Outcome::Malformed));
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![(String::from("user-1/phone"), Some(Outcome::Malformed), None)]
+        );
+    }
+
+    #[test]
+    fn test_multi_verifier_reports_attempt() {
+        use crate::multi::MultiVerifier;
+        use crate::otp::{Credential, Hotp, OtpGenerator, Totp};
+        let recorder = Recorder::default();
+        let phone = Totp::new(&String::from("abcdef234567abcdef234567"));
+        let token = Hotp::new(&String::from("zyxwvu765432zyxwvu765432"), &20);
+        let mut verifier = MultiVerifier::new(&[Credential::Totp(phone), Credential::Hotp(token)]);
+
+        let code = verifier.credentials()[1].generate_at(&1000).unwrap();
+        let auditor = Auditor::new("user-1", &recorder);
+        for _ in 0..2 {
+            match verifier.verify_audited_at(&code, &1000, &auditor) {
+                Ok(_) => (),
+                Err(e) => panic!("An error occurred when verifying the code: {}", e),
+            }
+        }
+
+        let events = recorder.events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(events[0].1.unwrap().is_valid());
+        assert_eq!(events[1].1, Some(Outcome::Invalid));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_shared_verifier_reports_lockout() {
+        use crate::otp::{OtpGenerator, Totp};
+        use crate::shared::SharedVerifier;
+        use crate::throttle::FixedLockout;
+        use std::sync::Arc;
+        let recorder = Arc::new(Recorder::default());
+        let policy = FixedLockout {
+            max_failures: 1,
+            lockout_secs: 60,
+        };
+        let verifier =
+            SharedVerifier::with_shards(policy, &1, &900).with_observer(recorder.clone());
+        let id = String::from("user-1/phone");
+        let totp = Totp::new(&String::from("abcdef234567abcdef234567"));
+        let code = totp.generate_at(&1000).unwrap();
+        let wrong = if code == "000000" { "111111" } else { "000000" };
+
+        for comparison in [wrong, code.as_str()] {
+            let _ = verifier.verify_totp_at(&id, &totp, &String::from(comparison), &1000);
+        }
+
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                (id.clone(), Some(Outcome::Invalid), Some(1060)),
+                (
+                    id.clone(),
+                    Some(Outcome::LockedOut { until: 1060 }),
+                    Some(1060)
+                ),
+            ]
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_tracing_observer() {
+        use crate::otp::Totp;
+        let mut audited = Audited::new(
+            &String::from("user-1/phone"),
+            Totp::new(&String::from("abcdef234567abcdef234567")),
+            &TracingObserver,
+        );
+
+        // Without a subscriber the events are discarded, this only checks that they are emitted.
+        assert!(audited.verify_at(&String::from("123456"), &1000).is_ok());
+    }
+}
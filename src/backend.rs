@@ -0,0 +1,191 @@
+// The HMAC backends that codes are generated with. Keys decoded from base-32 secrets use ring when
+// the ring feature is enabled and RustCrypto's hmac, sha1 and sha2 crates otherwise. Other
+// backends, such as hardware tokens, can be used by implementing HmacKey.
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+#[cfg(any(feature = "ring", feature = "rust-crypto"))]
+use crate::hotp::Algorithm;
+#[cfg(feature = "rust-crypto")]
+use alloc::string::String;
+#[cfg(any(feature = "ring", feature = "rust-crypto"))]
+use core::fmt;
+
+// The largest HMAC tag in bytes, that of HMAC-SHA-512.
+pub const MAX_TAG_BYTES: usize = 64;
+
+#[cfg(not(any(feature = "ring", feature = "rust-crypto")))]
+compile_error!("lotp needs an HMAC backend, enable the ring or rust-crypto feature.");
+
+// A key that can compute HMAC tags, with the hash algorithm fixed when the key is created.
+pub trait HmacKey {
+    // Compute the HMAC of a message, writing the tag into the start of out.
+    // message - The message to authenticate, for HOTP the big-endian counter.
+    // out - Where the tag is written.
+    // Returns the length of the tag.
+    fn sign(&self, message: &[u8], out: &mut [u8; MAX_TAG_BYTES]) -> Result<usize, Error>;
+}
+
+// An HMAC key computed with ring.
+#[cfg(feature = "ring")]
+pub struct RingKey {
+    algorithm: Algorithm, // The hash algorithm.
+    key: ring::hmac::Key, // The key.
+}
+
+#[cfg(feature = "ring")]
+impl RingKey {
+    // Creates a key.
+    // algorithm - The hash algorithm.
+    // secret - The raw secret.
+    pub fn new(algorithm: &Algorithm, secret: &[u8]) -> Result<RingKey, Error> {
+        let hmac_algorithm = match algorithm {
+            Algorithm::Sha1 => ring::hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+            Algorithm::Sha256 => ring::hmac::HMAC_SHA256,
+            Algorithm::Sha512 => ring::hmac::HMAC_SHA512,
+        };
+
+        return Ok(RingKey {
+            algorithm: *algorithm,
+            key: ring::hmac::Key::new(hmac_algorithm, secret),
+        });
+    }
+}
+
+#[cfg(feature = "ring")]
+impl HmacKey for RingKey {
+    fn sign(&self, message: &[u8], out: &mut [u8; MAX_TAG_BYTES]) -> Result<usize, Error> {
+        let tag = ring::hmac::sign(&self.key, message);
+        out[..tag.as_ref().len()].copy_from_slice(tag.as_ref());
+        return Ok(tag.as_ref().len());
+    }
+}
+
+#[cfg(feature = "ring")]
+impl fmt::Debug for RingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("RingKey")
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive();
+    }
+}
+
+// An HMAC key computed with RustCrypto's hmac, sha1 and sha2 crates.
+#[cfg(feature = "rust-crypto")]
+#[derive(Clone)]
+pub enum RustCryptoKey {
+    Sha1(hmac::Hmac<sha1::Sha1>),
+    Sha256(hmac::Hmac<sha2::Sha256>),
+    Sha512(hmac::Hmac<sha2::Sha512>),
+}
+
+#[cfg(feature = "rust-crypto")]
+impl RustCryptoKey {
+    // Creates a key.
+    // algorithm - The hash algorithm.
+    // secret - The raw secret.
+    pub fn new(algorithm: &Algorithm, secret: &[u8]) -> Result<RustCryptoKey, Error> {
+        use hmac::Mac;
+
+        let key = match algorithm {
+            Algorithm::Sha1 => hmac::Hmac::new_from_slice(secret).map(RustCryptoKey::Sha1),
+            Algorithm::Sha256 => hmac::Hmac::new_from_slice(secret).map(RustCryptoKey::Sha256),
+            Algorithm::Sha512 => hmac::Hmac::new_from_slice(secret).map(RustCryptoKey::Sha512),
+        };
+
+        // HMAC accepts keys of any length, so this only fails if the hmac crate changes.
+        match key {
+            Ok(k) => return Ok(k),
+            Err(_) => {
+                return Err(Error::Backend(String::from(
+                    "The key was rejected by the hmac crate.",
+                )))
+            }
+        }
+    }
+
+    // Returns the hash algorithm.
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            RustCryptoKey::Sha1(_) => return Algorithm::Sha1,
+            RustCryptoKey::Sha256(_) => return Algorithm::Sha256,
+            RustCryptoKey::Sha512(_) => return Algorithm::Sha512,
+        }
+    }
+}
+
+#[cfg(feature = "rust-crypto")]
+impl HmacKey for RustCryptoKey {
+    fn sign(&self, message: &[u8], out: &mut [u8; MAX_TAG_BYTES]) -> Result<usize, Error> {
+        use hmac::Mac;
+
+        fn finish<M: Mac>(mut mac: M, message: &[u8], out: &mut [u8; MAX_TAG_BYTES]) -> usize {
+            mac.update(message);
+            let tag = mac.finalize().into_bytes();
+            out[..tag.len()].copy_from_slice(&tag);
+            return tag.len();
+        }
+
+        match self {
+            RustCryptoKey::Sha1(m) => return Ok(finish(m.clone(), message, out)),
+            RustCryptoKey::Sha256(m) => return Ok(finish(m.clone(), message, out)),
+            RustCryptoKey::Sha512(m) => return Ok(finish(m.clone(), message, out)),
+        }
+    }
+}
+
+#[cfg(feature = "rust-crypto")]
+impl fmt::Debug for RustCryptoKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("RustCryptoKey")
+            .field("algorithm", &self.algorithm())
+            .finish_non_exhaustive();
+    }
+}
+
+// The key that base-32 secrets are decoded into.
+#[cfg(feature = "ring")]
+pub type SoftwareKey = RingKey;
+// The key that base-32 secrets are decoded into.
+#[cfg(all(feature = "rust-crypto", not(feature = "ring")))]
+pub type SoftwareKey = RustCryptoKey;
+
+// Compare a computed code with a submitted one in constant time, so that the time taken doesn't
+// reveal how close the submitted code was.
+#[cfg(feature = "ring")]
+pub(crate) fn codes_equal(a: &u64, b: &u64) -> bool {
+    return ring::constant_time::verify_slices_are_equal(&a.to_be_bytes(), &b.to_be_bytes())
+        .is_ok();
+}
+
+// Compare a computed code with a submitted one in constant time, so that the time taken doesn't
+// reveal how close the submitted code was.
+#[cfg(all(feature = "rust-crypto", not(feature = "ring")))]
+pub(crate) fn codes_equal(a: &u64, b: &u64) -> bool {
+    return bool::from(subtle::ConstantTimeEq::ct_eq(a, b));
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    #[cfg(all(feature = "ring", feature = "rust-crypto"))]
+    fn test_backends_agree() {
+        use super::*;
+        let secret = b"12345678901234567890";
+        for algorithm in [Algorithm::Sha1, Algorithm::Sha256, Algorithm::Sha512] {
+            let (mut ring_tag, mut rust_tag) = ([0u8; MAX_TAG_BYTES], [0u8; MAX_TAG_BYTES]);
+            let ring_len = RingKey::new(&algorithm, secret)
+                .unwrap()
+                .sign(b"counter", &mut ring_tag)
+                .unwrap();
+            let rust_len = RustCryptoKey::new(&algorithm, secret)
+                .unwrap()
+                .sign(b"counter", &mut rust_tag)
+                .unwrap();
+
+            assert_eq!(ring_len, rust_len);
+            assert_eq!(ring_tag, rust_tag);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+// Generating or verifying codes for many credentials at once against a single clock snapshot, such
+// as for monitoring or bulk provisioning. With the parallel feature the credentials are spread over
+// rayon's thread pool. Results are returned in the same order as the credentials.
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use crate::otp::{Credential, OtpGenerator, OtpVerifier};
+use crate::outcome::Outcome;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+// Generate the code of every credential at the time now.
+// credentials - The credentials to generate codes for.
+// now - The unix time in seconds shared by every credential.
+pub fn generate_batch(credentials: &[Credential], now: &u64) -> Vec<Result<String, Error>> {
+    #[cfg(feature = "parallel")]
+    return credentials.par_iter().map(|c| c.generate_at(now)).collect();
+
+    #[cfg(not(feature = "parallel"))]
+    return credentials.iter().map(|c| c.generate_at(now)).collect();
+}
+
+// Verify a code for every credential at the time now, updating the state of credentials that
+// accept their code. The credentials should be persisted afterwards.
+// credentials - The credentials to verify codes for.
+// comparisons - The code for each credential, nothing is verified unless there is exactly one code
+// for every credential.
+// now - The unix time in seconds shared by every credential.
+pub fn verify_batch(
+    credentials: &mut [Credential],
+    comparisons: &[String],
+    now: &u64,
+) -> Result<Vec<Result<Outcome, Error>>, Error> {
+    if credentials.len() != comparisons.len() {
+        return Err(Error::BatchLength {
+            credentials: credentials.len(),
+            codes: comparisons.len(),
+        });
+    }
+
+    #[cfg(feature = "parallel")]
+    return Ok(credentials
+        .par_iter_mut()
+        .zip(comparisons.par_iter())
+        .map(|(c, code)| c.verify_at(code, now))
+        .collect());
+
+    #[cfg(not(feature = "parallel"))]
+    return Ok(credentials
+        .iter_mut()
+        .zip(comparisons.iter())
+        .map(|(c, code)| c.verify_at(code, now))
+        .collect());
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_batch() {
+        use super::*;
+        use crate::hotp::Digits;
+        use crate::otp::{Hotp, Totp};
+        let mut credentials: Vec<Credential> = (0..50)
+            .map(|i| {
+                let secret = format!("abcdef234567abcdef2345{}", (i % 6) + 2);
+                if i % 2 == 0 {
+                    Credential::Hotp(Hotp::new(&secret, &(i as u64)))
+                } else {
+                    let mut totp = Totp::new(&secret);
+                    totp.digits = Digits::Eight;
+                    Credential::Totp(totp)
+                }
+            })
+            .collect();
+        credentials.push(Credential::Totp(Totp::new(&String::from("1"))));
+
+        let codes = generate_batch(&credentials, &1000);
+        assert_eq!(codes.len(), 51);
+        assert_eq!(codes[1], credentials[1].generate_at(&1000));
+        assert_eq!(codes[50], Err(Error::NonBase32 { position: 0 }));
+
+        let comparisons: Vec<String> = codes
+            .iter()
+            .map(|c| c.clone().unwrap_or_default())
+            .collect();
+        assert_eq!(
+            verify_batch(&mut credentials, &comparisons[1..], &1000),
+            Err(Error::BatchLength {
+                credentials: 51,
+                codes: 50,
+            })
+        );
+
+        let outcomes = match verify_batch(&mut credentials, &comparisons, &1000) {
+            Ok(o) => o,
+            Err(e) => panic!("An error occurred when verifying the batch: {}", e),
+        };
+
+        for (i, outcome) in outcomes.iter().enumerate().take(50) {
+            match outcome {
+                Ok(o) => assert!(o.is_valid(), "{}", i),
+                Err(e) => panic!("An error occurred when verifying the code: {}", e),
+            }
+        }
+        assert!(outcomes[50].is_err());
+
+        match &credentials[0] {
+            Credential::Hotp(h) => assert_eq!(h.counter, 1),
+            _ => panic!("The credentials were reordered."),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+// A generated code held on the stack, so that generating a code doesn't allocate. It can be
+// displayed, borrowed as a str or copied into a buffer owned by the caller.
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use crate::hotp::Digits;
+use alloc::string::String;
+use core::fmt;
+
+// The largest amount of digits a code can have.
+const MAX_DIGITS: usize = 8;
+
+// A code of 6 to 8 ASCII digits, including any leading zeroes.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Code {
+    digits: [u8; MAX_DIGITS], // The ASCII digits, only the first len are used.
+    len: u8,                  // The amount of digits in the code.
+}
+
+impl Code {
+    // Creates a code from a truncated HOTP value that has already been reduced to the amount of
+    // digits, prepending zeroes as required.
+    pub(crate) fn new(value: &u64, digits: &Digits) -> Code {
+        let len = digits.count();
+        let mut code = Code {
+            digits: [b'0'; MAX_DIGITS],
+            len: len as u8,
+        };
+
+        let mut remaining = *value;
+        for i in (0..len).rev() {
+            code.digits[i] = b'0' + (remaining % 10) as u8;
+            remaining /= 10;
+        }
+
+        return code;
+    }
+
+    // Returns the code as a str.
+    pub fn as_str(&self) -> &str {
+        // The code is only ever made up of ASCII digits.
+        return core::str::from_utf8(&self.digits[..self.len as usize]).unwrap_or_default();
+    }
+
+    // Copy the code into the start of a buffer and return the amount of bytes written.
+    // buf - The buffer, it must be able to hold every digit of the code.
+    pub fn write_to(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let len = self.len as usize;
+        if buf.len() < len {
+            return Err(Error::BufferTooSmall {
+                needed: len,
+                len: buf.len(),
+            });
+        }
+
+        buf[..len].copy_from_slice(&self.digits[..len]);
+        return Ok(len);
+    }
+}
+
+impl AsRef<str> for Code {
+    fn as_ref(&self) -> &str {
+        return self.as_str();
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str(self.as_str());
+    }
+}
+
+impl fmt::Debug for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.debug_tuple("Code").field(&self.as_str()).finish();
+    }
+}
+
+impl PartialEq<str> for Code {
+    fn eq(&self, other: &str) -> bool {
+        return self.as_str() == other;
+    }
+}
+
+impl PartialEq<String> for Code {
+    fn eq(&self, other: &String) -> bool {
+        return self.as_str() == other;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_code() {
+        use super::*;
+        let code = Code::new(&1234, &Digits::Six);
+        assert_eq!(code.as_str(), "001234");
+        assert_eq!(code.to_string(), "001234");
+        assert_eq!(code, String::from("001234"));
+        assert_eq!(format!("{:?}", code), "Code(\"001234\")");
+
+        let mut buf = [0u8; 8];
+        match code.write_to(&mut buf) {
+            Ok(len) => assert_eq!(&buf[..len], b"001234"),
+            Err(e) => panic!("An error occurred when writing the code: {}", e),
+        }
+
+        let code = Code::new(&87654321, &Digits::Eight);
+        assert_eq!(
+            code.write_to(&mut buf[..7]),
+            Err(Error::BufferTooSmall { needed: 8, len: 7 })
+        );
+        assert_eq!(code.as_ref(), "87654321");
+    }
+}
@@ -0,0 +1,208 @@
+// Deriving each user's secret from a server master key and a stable user identifier with HKDF
+// (RFC 5869), so that no per-user secret has to be stored. The master key version used for a user
+// should be stored with the user so that the master key can be rotated, RotatingCredential can be
+// used to accept codes from both the old and new secret while users re-enroll.
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use crate::secret::{DEFAULT_SECRET_BYTES, MIN_SECRET_BYTES};
+use ring::hkdf;
+use std::fmt;
+
+// The context every derived secret is bound to, the version changes if the derivation does.
+const CONTEXT: &[u8] = b"lotp otp secret v1";
+
+// A master key along with its version.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MasterKey {
+    version: u32,
+    key: Vec<u8>,
+}
+
+impl MasterKey {
+    // Creates a master key.
+    // version - The version of the key, stored alongside users whose secret it derived.
+    // key - The key material, at least MIN_SECRET_BYTES bytes from a secure random source.
+    pub fn new(version: &u32, key: &[u8]) -> Result<MasterKey, Error> {
+        if key.len() < MIN_SECRET_BYTES {
+            return Err(Error::SecretTooShort(key.len()));
+        }
+
+        return Ok(MasterKey {
+            version: *version,
+            key: key.to_vec(),
+        });
+    }
+
+    // Returns the version of the key.
+    pub fn version(&self) -> u32 {
+        return self.version;
+    }
+
+    // Derive a user's secret, encoded as base-32 without padding. The same key, version and user
+    // identifier always derive the same secret.
+    // user_id - A stable identifier for the user that is never reused, such as a database ID.
+    pub fn derive_secret(&self, user_id: &String) -> Result<String, Error> {
+        let version = self.version.to_be_bytes();
+        let id_len = (user_id.len() as u64).to_be_bytes();
+        let info = [CONTEXT, &version, &id_len, user_id.as_bytes()];
+
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, CONTEXT).extract(&self.key);
+        let mut secret = [0u8; DEFAULT_SECRET_BYTES];
+
+        // Expanding only fails when more than 255 blocks are requested.
+        match prk
+            .expand(&info, SecretLength)
+            .and_then(|okm| okm.fill(&mut secret))
+        {
+            Ok(_) => {
+                return Ok(base32::encode(
+                    base32::Alphabet::RFC4648 { padding: false },
+                    &secret,
+                ))
+            }
+            Err(_) => return Err(Error::KeyDerivation),
+        }
+    }
+}
+
+// The key material is never printed.
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("MasterKey")
+            .field("version", &self.version)
+            .finish_non_exhaustive();
+    }
+}
+
+struct SecretLength;
+
+impl hkdf::KeyType for SecretLength {
+    fn len(&self) -> usize {
+        return DEFAULT_SECRET_BYTES;
+    }
+}
+
+// Every master key version still in use, new secrets are derived with the current one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MasterKeyRing {
+    keys: Vec<MasterKey>, // The known keys.
+    current: u32,         // The version used for new secrets.
+}
+
+impl MasterKeyRing {
+    // Creates a key ring with a single key, which is current.
+    // key - The master key.
+    pub fn new(key: &MasterKey) -> MasterKeyRing {
+        return MasterKeyRing {
+            keys: vec![key.clone()],
+            current: key.version,
+        };
+    }
+
+    // Adds a key and makes it current, replacing any key with the same version.
+    // key - The new master key.
+    pub fn rotate(&mut self, key: &MasterKey) {
+        self.keys.retain(|k| k.version != key.version);
+        self.keys.push(key.clone());
+        self.current = key.version;
+    }
+
+    // Removes a key once no users' secrets are derived from it, the current key can't be removed.
+    // version - The version of the key to remove.
+    pub fn retire(&mut self, version: &u32) -> bool {
+        if *version == self.current {
+            return false;
+        }
+
+        let before = self.keys.len();
+        self.keys.retain(|k| k.version != *version);
+        return self.keys.len() != before;
+    }
+
+    // Returns the version used for new secrets.
+    pub fn current_version(&self) -> u32 {
+        return self.current;
+    }
+
+    // Derive a user's secret with the current key, returning the key version to store with the
+    // user along with the secret.
+    // user_id - A stable identifier for the user that is never reused.
+    pub fn derive_current(&self, user_id: &String) -> Result<(u32, String), Error> {
+        match self.derive_secret(user_id, &self.current) {
+            Ok(s) => return Ok((self.current, s)),
+            Err(e) => return Err(e),
+        }
+    }
+
+    // Derive a user's secret with the key of a given version.
+    // user_id - A stable identifier for the user that is never reused.
+    // version - The version of the key stored with the user.
+    pub fn derive_secret(&self, user_id: &String, version: &u32) -> Result<String, Error> {
+        match self.keys.iter().find(|k| k.version == *version) {
+            Some(k) => return k.derive_secret(user_id),
+            None => return Err(Error::UnknownKeyVersion(*version)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_derive_secret() {
+        use super::*;
+        use crate::otp::{OtpGenerator, OtpVerifier, Totp};
+        let key = match MasterKey::new(&1, &[0x55u8; 32]) {
+            Ok(k) => k,
+            Err(e) => panic!("An error occurred when creating the master key: {}", e),
+        };
+
+        let alice = match key.derive_secret(&String::from("alice")) {
+            Ok(s) => s,
+            Err(e) => panic!("An error occurred when deriving the secret: {}", e),
+        };
+        assert_eq!(alice.len(), 32);
+        assert_eq!(Ok(alice.clone()), key.derive_secret(&String::from("alice")));
+        assert_ne!(Ok(alice.clone()), key.derive_secret(&String::from("bob")));
+        assert_ne!(
+            Ok(alice.clone()),
+            MasterKey::new(&2, &[0x55u8; 32])
+                .unwrap()
+                .derive_secret(&String::from("alice"))
+        );
+        assert_eq!(format!("{:?}", key), "MasterKey { version: 1, .. }");
+
+        // The derived secret works with the existing credentials.
+        let mut totp = Totp::new(&alice);
+        let code = totp.generate_at(&1000).unwrap();
+        match totp.verify_at(&code, &1000) {
+            Ok(o) => assert!(o.is_valid()),
+            Err(e) => panic!("An error occurred when verifying the totp: {}", e),
+        }
+
+        assert_eq!(MasterKey::new(&1, &[7u8; 8]), Err(Error::SecretTooShort(8)));
+    }
+
+    #[test]
+    fn test_master_key_rotation() {
+        use super::*;
+        let old = MasterKey::new(&1, &[1u8; 32]).unwrap();
+        let new = MasterKey::new(&2, &[2u8; 32]).unwrap();
+        let user = String::from("42");
+        let mut ring = MasterKeyRing::new(&old);
+        let (version, secret) = ring.derive_current(&user).unwrap();
+        assert_eq!(version, 1);
+
+        ring.rotate(&new);
+        assert_eq!(ring.current_version(), 2);
+        assert_eq!(ring.derive_secret(&user, &1), Ok(secret.clone()));
+        assert_ne!(ring.derive_current(&user).unwrap().1, secret);
+
+        assert!(!ring.retire(&2));
+        assert!(ring.retire(&1));
+        assert_eq!(
+            ring.derive_secret(&user, &1),
+            Err(Error::UnknownKeyVersion(1))
+        );
+    }
+}
@@ -0,0 +1,140 @@
+// Tracks how far a TOTP device's clock tends to be from the server's, measured in time steps.
+// Every accepted code records the difference between the step it matched and the server's current
+// step, and an exponentially weighted moving average of those differences is kept. The estimate is
+// stored in thousandths of a step so that it can be persisted as an integer.
+#![allow(clippy::needless_return)]
+
+// The weight given to each new sample, as a fraction of 1/SMOOTHING.
+const SMOOTHING: i64 = 4;
+
+// How many verification windows the estimate may move away from the server's time step, a device
+// that keeps matching at the edge of the window can't walk the centre any further than this.
+pub const MAX_DRIFT_WINDOWS: u64 = 2;
+
+// The smoothed clock drift of a single credential.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DriftState {
+    estimate_millis: i64, // The smoothed drift in thousandths of a time step.
+    samples: u32,         // The amount of accepted codes that have been recorded.
+}
+
+impl DriftState {
+    // Creates the state for a credential without any recorded drift.
+    pub fn new() -> DriftState {
+        return DriftState {
+            estimate_millis: 0,
+            samples: 0,
+        };
+    }
+
+    // Creates the state from persisted values.
+    // estimate_millis - The smoothed drift in thousandths of a time step.
+    // samples - The amount of accepted codes that have been recorded.
+    pub fn from_parts(estimate_millis: &i64, samples: &u32) -> DriftState {
+        return DriftState {
+            estimate_millis: *estimate_millis,
+            samples: *samples,
+        };
+    }
+
+    // Returns the smoothed drift in thousandths of a time step.
+    pub fn estimate_millis(&self) -> i64 {
+        return self.estimate_millis;
+    }
+
+    // Returns the amount of accepted codes that have been recorded.
+    pub fn samples(&self) -> u32 {
+        return self.samples;
+    }
+
+    // Returns the estimated drift rounded to a whole amount of time steps, this is the offset from
+    // the current time step that verification windows should be centred on.
+    pub fn centre(&self) -> i64 {
+        let half = if self.estimate_millis < 0 { -500 } else { 500 };
+        return self.estimate_millis.saturating_add(half) / 1000;
+    }
+
+    // Records the time step difference of an accepted code.
+    // step_delta - The matched time step minus the server's current time step.
+    // max_steps - The furthest the estimate may be from the server's time step.
+    pub fn record(&mut self, step_delta: &i64, max_steps: &u64) {
+        let sample = step_delta.saturating_mul(1000);
+
+        if self.samples == 0 {
+            self.estimate_millis = sample;
+        } else {
+            // Persisted estimates can hold any value, so the arithmetic saturates.
+            let step = sample.saturating_sub(self.estimate_millis) / SMOOTHING;
+            self.estimate_millis = self.estimate_millis.saturating_add(step);
+        }
+
+        let bound = i64::try_from(*max_steps)
+            .unwrap_or(i64::MAX)
+            .saturating_mul(1000);
+        self.estimate_millis = self.estimate_millis.clamp(-bound, bound);
+
+        self.samples = self.samples.saturating_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_drift_smoothing() {
+        use super::*;
+        let mut drift = DriftState::new();
+        assert_eq!(drift.centre(), 0);
+
+        drift.record(&2, &4);
+        assert_eq!(drift.estimate_millis(), 2000);
+
+        drift.record(&0, &4);
+        assert_eq!(drift.estimate_millis(), 1500);
+        assert_eq!(drift.centre(), 2);
+
+        drift.record(&0, &4);
+        drift.record(&0, &4);
+        assert_eq!(drift.centre(), 1);
+        assert_eq!(drift.samples(), 4);
+    }
+
+    #[test]
+    fn test_drift_negative_centre() {
+        use super::*;
+        let drift = DriftState::from_parts(&-1600, &3);
+        assert_eq!(drift.centre(), -2);
+    }
+
+    #[test]
+    fn test_drift_extreme_estimate() {
+        use super::*;
+        let mut drift = DriftState::from_parts(&i64::MAX, &3);
+        assert_eq!(drift.centre(), i64::MAX / 1000);
+        drift.record(&i64::MIN, &u64::MAX);
+
+        let mut drift = DriftState::from_parts(&i64::MIN, &3);
+        assert_eq!(drift.centre(), i64::MIN / 1000);
+        drift.record(&i64::MAX, &u64::MAX);
+        assert!(drift.estimate_millis() < 0);
+    }
+
+    #[test]
+    fn test_drift_bounded() {
+        use super::*;
+        let mut drift = DriftState::new();
+
+        // A device that always matches at the edge of the window can't move the centre past the
+        // bound, even though every sample is further out than the last.
+        for delta in 1..50 {
+            drift.record(&delta, &4);
+        }
+        assert_eq!(drift.estimate_millis(), 4000);
+        assert_eq!(drift.centre(), 4);
+
+        for delta in 1..50 {
+            drift.record(&-delta, &4);
+        }
+        assert_eq!(drift.centre(), -4);
+    }
+}
@@ -0,0 +1,376 @@
+// Enrolling a user in TOTP. A pending enrollment holds a newly generated secret that is shown to
+// the user as an otpauth URI or QR code, and the credential is only activated once the user has
+// entered a valid code from their device. A pending enrollment can be serialized between requests,
+// its secret is always included since the enrollment is useless without it.
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use crate::hotp::{Algorithm, Digits};
+use crate::otp::{Credential, OtpVerifier, Totp};
+use crate::outcome::Outcome;
+use crate::secret::{self, DEFAULT_SECRET_BYTES};
+use crate::uri;
+use crate::verifier::Verifier;
+use core::fmt;
+
+// The amount of seconds a user has to confirm an enrollment by default.
+pub const DEFAULT_CONFIRMATION_WINDOW: u64 = 600;
+// The amount of wrong codes that can be entered before an enrollment expires.
+pub const MAX_CONFIRMATION_ATTEMPTS: u32 = 5;
+
+// The stages of an enrollment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnrollmentState {
+    Pending,   // Waiting for the user to enter their first code.
+    Confirmed, // A valid code was entered, the credential can be activated.
+    Expired,   // The confirmation window passed or too many wrong codes were entered.
+}
+
+// A TOTP credential that is waiting to be confirmed by the user.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Enrollment {
+    secret: String,       // The base-32 secret given to the user.
+    digits: Digits,       // The amount of digits in a code.
+    algorithm: Algorithm, // The hash algorithm used to generate codes.
+    period: u64,          // The amount of seconds that each code is valid for.
+    skew: u16,            // The amount of time steps either side of the current one accepted.
+    issuer: String,       // The provider or service shown in the user's authenticator.
+    account: String,      // The account name shown in the user's authenticator.
+    expires_at: u64,      // The unix time at which the enrollment can no longer be confirmed.
+    confirmed: bool,      // Whether a valid code has been entered.
+    #[cfg_attr(feature = "serde", serde(default))]
+    attempts: u32, // The amount of wrong codes entered.
+    verifier: Verifier,   // The state recorded by the confirming code, so it can't be replayed.
+}
+
+impl Enrollment {
+    // Start an enrollment for a 6 digit SHA-1 TOTP credential with a newly generated secret.
+    // issuer - The provider or service shown in the user's authenticator.
+    // account - The account name shown in the user's authenticator.
+    // now - The current unix time in seconds.
+    // window_secs - The amount of seconds the user has to confirm the enrollment.
+    pub fn start(
+        issuer: &String,
+        account: &String,
+        now: &u64,
+        window_secs: &u64,
+    ) -> Result<Enrollment, Error> {
+        match secret::generate_secret(&DEFAULT_SECRET_BYTES) {
+            Ok(s) => {
+                return Ok(Enrollment::from_totp(
+                    &Totp::new(&s),
+                    issuer,
+                    account,
+                    now,
+                    window_secs,
+                ))
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    // Start an enrollment for a configured TOTP credential, its verifier state is discarded.
+    // totp - The credential to enroll, including its secret.
+    // issuer - The provider or service shown in the user's authenticator.
+    // account - The account name shown in the user's authenticator.
+    // now - The current unix time in seconds.
+    // window_secs - The amount of seconds the user has to confirm the enrollment.
+    #[allow(clippy::ptr_arg)]
+    pub fn from_totp(
+        totp: &Totp,
+        issuer: &String,
+        account: &String,
+        now: &u64,
+        window_secs: &u64,
+    ) -> Enrollment {
+        return Enrollment {
+            secret: totp.secret.clone(),
+            digits: totp.digits,
+            algorithm: totp.algorithm,
+            period: totp.period,
+            skew: totp.skew,
+            issuer: issuer.clone(),
+            account: account.clone(),
+            expires_at: now.saturating_add(*window_secs),
+            confirmed: false,
+            attempts: 0,
+            verifier: Verifier::new(),
+        };
+    }
+
+    // Returns the stage of the enrollment at the time now.
+    // now - The current unix time in seconds.
+    pub fn state(&self, now: &u64) -> EnrollmentState {
+        if self.confirmed {
+            return EnrollmentState::Confirmed;
+        } else if *now >= self.expires_at || self.attempts >= MAX_CONFIRMATION_ATTEMPTS {
+            return EnrollmentState::Expired;
+        }
+
+        return EnrollmentState::Pending;
+    }
+
+    // Returns the unix time at which the enrollment can no longer be confirmed.
+    pub fn expires_at(&self) -> u64 {
+        return self.expires_at;
+    }
+
+    // Returns the amount of wrong codes entered.
+    pub fn attempts(&self) -> u32 {
+        return self.attempts;
+    }
+
+    // Returns the base-32 secret, for users that can't scan a QR code.
+    pub fn secret(&self) -> &String {
+        return &self.secret;
+    }
+
+    // Returns the otpauth URI that authenticator apps use to add the credential.
+    pub fn uri(&self) -> String {
+        return uri::credential_uri(&Credential::Totp(self.totp()), &self.issuer, &self.account);
+    }
+
+    // Returns the otpauth URI rendered as a QR code in an SVG document.
+    #[cfg(feature = "qr")]
+    pub fn qr_svg(&self) -> Result<String, Error> {
+        match qrcode::QrCode::new(self.uri().as_bytes()) {
+            Ok(code) => {
+                return Ok(code
+                    .render::<qrcode::render::svg::Color>()
+                    .min_dimensions(200, 200)
+                    .build())
+            }
+            Err(_) => {
+                return Err(Error::InvalidUri(String::from(
+                    "The URI is too long for a QR code.",
+                )))
+            }
+        }
+    }
+
+    // Confirm the enrollment with the first code from the user's device. The enrollment is
+    // confirmed when the outcome is valid, otherwise it stays pending and another code can be tried
+    // until MAX_CONFIRMATION_ATTEMPTS wrong codes have been entered, after which it expires.
+    // comparison - The code entered by the user.
+    // now - The current unix time in seconds.
+    #[allow(clippy::needless_late_init)]
+    pub fn confirm(&mut self, comparison: &String, now: &u64) -> Result<Outcome, Error> {
+        match self.state(now) {
+            EnrollmentState::Confirmed => return Err(Error::EnrollmentConfirmed),
+            EnrollmentState::Expired => return Err(Error::EnrollmentExpired),
+            EnrollmentState::Pending => (),
+        }
+
+        let mut totp = self.totp();
+        let outcome;
+        match totp.verify_at(comparison, now) {
+            Ok(o) => outcome = o,
+            Err(e) => return Err(e),
+        }
+
+        match outcome {
+            Outcome::Valid { .. } => {
+                self.confirmed = true;
+                self.verifier = totp.verifier;
+            }
+            Outcome::Malformed => (),
+            _ => self.attempts = self.attempts.saturating_add(1),
+        }
+
+        return Ok(outcome);
+    }
+
+    // Returns the credential to activate once the enrollment is confirmed.
+    pub fn credential(&self) -> Option<Totp> {
+        if !self.confirmed {
+            return None;
+        }
+
+        return Some(self.totp());
+    }
+
+    fn totp(&self) -> Totp {
+        return Totp {
+            secret: self.secret.clone(),
+            digits: self.digits,
+            algorithm: self.algorithm,
+            period: self.period,
+            skew: self.skew,
+            verifier: self.verifier,
+        };
+    }
+}
+
+// The secret is never printed, like the Debug output of Totp.
+impl fmt::Debug for Enrollment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("Enrollment")
+            .field("secret", &"<redacted>")
+            .field("digits", &self.digits)
+            .field("algorithm", &self.algorithm)
+            .field("period", &self.period)
+            .field("skew", &self.skew)
+            .field("issuer", &self.issuer)
+            .field("account", &self.account)
+            .field("expires_at", &self.expires_at)
+            .field("confirmed", &self.confirmed)
+            .field("attempts", &self.attempts)
+            .field("verifier", &self.verifier)
+            .finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_enrollment_confirm() {
+        use super::*;
+        use crate::otp::OtpGenerator;
+        let issuer = String::from("Example");
+        let account = String::from("alice@example.com");
+        let mut enrollment = match Enrollment::start(&issuer, &account, &1000, &300) {
+            Ok(e) => e,
+            Err(e) => panic!("An error occurred when starting the enrollment: {}", e),
+        };
+
+        assert_eq!(enrollment.state(&1000), EnrollmentState::Pending);
+        assert!(enrollment.credential().is_none());
+        assert!(!format!("{:?}", enrollment).contains(enrollment.secret().as_str()));
+        assert!(enrollment
+            .uri()
+            .starts_with("otpauth://totp/Example:alice%40example.com?"));
+
+        let code = match Totp::new(enrollment.secret()).generate_at(&1100) {
+            Ok(c) => c,
+            Err(e) => panic!("An error occurred when generating the totp: {}", e),
+        };
+
+        let wrong: String = code
+            .chars()
+            .map(|c| char::from(b'0' + (c as u8 - b'0' + 1) % 10))
+            .collect();
+        match enrollment.confirm(&wrong, &1100) {
+            Ok(o) => assert_eq!(o, Outcome::Invalid),
+            Err(e) => panic!("An error occurred when confirming the enrollment: {}", e),
+        }
+
+        match enrollment.confirm(&code, &1100) {
+            Ok(o) => assert!(o.is_valid()),
+            Err(e) => panic!("An error occurred when confirming the enrollment: {}", e),
+        }
+
+        assert_eq!(enrollment.state(&5000), EnrollmentState::Confirmed);
+        assert_eq!(
+            enrollment.confirm(&code, &1100),
+            Err(Error::EnrollmentConfirmed)
+        );
+
+        // The confirming code can't be used again on the activated credential.
+        let mut totp = enrollment.credential().unwrap();
+        match totp.verify_at(&code, &1100) {
+            Ok(o) => assert_eq!(o, Outcome::Replayed),
+            Err(e) => panic!("An error occurred when verifying the totp: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_enrollment_expired() {
+        use super::*;
+        use crate::otp::OtpGenerator;
+        let totp = Totp::new(&String::from("abcdef234567abcdef234567"));
+        let mut enrollment = Enrollment::from_totp(
+            &totp,
+            &String::from("Example"),
+            &String::from("bob"),
+            &1000,
+            &300,
+        );
+
+        let code = totp.generate_at(&1300).unwrap();
+        assert_eq!(enrollment.state(&1300), EnrollmentState::Expired);
+        assert_eq!(
+            enrollment.confirm(&code, &1300),
+            Err(Error::EnrollmentExpired)
+        );
+        assert!(enrollment.credential().is_none());
+    }
+
+    #[test]
+    fn test_enrollment_attempts() {
+        use super::*;
+        use crate::otp::OtpGenerator;
+        let totp = Totp::new(&String::from("abcdef234567abcdef234567"));
+        let mut enrollment = Enrollment::from_totp(
+            &totp,
+            &String::from("Example"),
+            &String::from("bob"),
+            &1000,
+            &300,
+        );
+
+        let code = totp.generate_at(&1100).unwrap();
+        let wrong = if code == "000000" { "111111" } else { "000000" };
+
+        // Malformed codes were never checked, so they don't count.
+        match enrollment.confirm(&String::from("12345"), &1100) {
+            Ok(o) => assert_eq!(o, Outcome::Malformed),
+            Err(e) => panic!("An error occurred when confirming the enrollment: {}", e),
+        }
+
+        for attempt in 1..=MAX_CONFIRMATION_ATTEMPTS {
+            assert_eq!(enrollment.state(&1100), EnrollmentState::Pending);
+            match enrollment.confirm(&String::from(wrong), &1100) {
+                Ok(o) => assert_eq!(o, Outcome::Invalid),
+                Err(e) => panic!("An error occurred when confirming the enrollment: {}", e),
+            }
+            assert_eq!(enrollment.attempts(), attempt);
+        }
+
+        // The last wrong code expires the enrollment, so even the right code is refused.
+        assert_eq!(enrollment.state(&1100), EnrollmentState::Expired);
+        assert_eq!(
+            enrollment.confirm(&code, &1100),
+            Err(Error::EnrollmentExpired)
+        );
+        assert!(enrollment.credential().is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_enrollment_serde() {
+        use super::*;
+        let enrollment = Enrollment::start(
+            &String::from("Example"),
+            &String::from("bob"),
+            &0,
+            &DEFAULT_CONFIRMATION_WINDOW,
+        )
+        .unwrap();
+
+        // The pending secret is kept even when credential secrets are skipped.
+        let json = serde_json::to_string(&enrollment).unwrap();
+        assert!(json.contains(enrollment.secret().as_str()));
+
+        let restored: Enrollment = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, enrollment);
+    }
+
+    #[cfg(feature = "qr")]
+    #[test]
+    fn test_enrollment_qr_svg() {
+        use super::*;
+        let enrollment = Enrollment::start(
+            &String::from("Example"),
+            &String::from("bob"),
+            &0,
+            &DEFAULT_CONFIRMATION_WINDOW,
+        )
+        .unwrap();
+
+        match enrollment.qr_svg() {
+            Ok(svg) => assert!(svg.contains("<svg")),
+            Err(e) => panic!("An error occurred when rendering the qr code: {}", e),
+        }
+    }
+}
@@ -0,0 +1,378 @@
+// Envelope encryption of secrets at rest with AES-256-GCM. Every secret is encrypted under its own
+// random data key, which is in turn encrypted (wrapped) under a key-encryption key. Re-wrapping a
+// sealed secret under a new key-encryption key only re-encrypts the data key.
+//
+// A sealed secret is the text lotp:1:<key id>:<payload>, where 1 is the format version and the
+// payload is base-32 without padding of the data key nonce, the wrapped data key, the secret nonce
+// and the encrypted secret. Both encryptions are bound to the credential ID as associated data, so
+// a sealed secret copied to another credential can't be opened.
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fmt;
+
+const PREFIX: &str = "lotp";
+const FORMAT_VERSION: u32 = 1;
+const KEY_LEN: usize = 32;
+const WRAPPED_KEY_LEN: usize = KEY_LEN + 16;
+
+// A key-encryption key along with its ID, which is written into every secret it seals.
+pub struct KeyEncryptionKey {
+    id: u32,
+    key: LessSafeKey,
+}
+
+impl KeyEncryptionKey {
+    // Creates a key-encryption key.
+    // id - The ID of the key, used to find it when opening a sealed secret.
+    // key - 32 bytes of key material from a secure random source or a key management service.
+    pub fn new(id: &u32, key: &[u8]) -> Result<KeyEncryptionKey, Error> {
+        match new_key(key) {
+            Ok(k) => return Ok(KeyEncryptionKey { id: *id, key: k }),
+            Err(e) => return Err(e),
+        }
+    }
+
+    // Returns the ID of the key.
+    pub fn id(&self) -> u32 {
+        return self.id;
+    }
+}
+
+// The key material is never printed.
+impl fmt::Debug for KeyEncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("KeyEncryptionKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive();
+    }
+}
+
+// Seal a secret for storage.
+// kek - The key-encryption key to wrap the data key with.
+// credential_id - The ID of the credential the secret belongs to.
+// secret - The base-32 secret.
+#[allow(clippy::needless_late_init)]
+pub fn seal_secret(
+    kek: &KeyEncryptionKey,
+    credential_id: &String,
+    secret: &String,
+) -> Result<String, Error> {
+    let rng = SystemRandom::new();
+    let mut data_key = [0u8; KEY_LEN];
+    if rng.fill(&mut data_key).is_err() {
+        return Err(Error::RandomFailure);
+    }
+
+    let key;
+    match new_key(&data_key) {
+        Ok(k) => key = k,
+        Err(e) => return Err(e),
+    }
+
+    let mut payload = Vec::with_capacity(2 * NONCE_LEN + WRAPPED_KEY_LEN + secret.len() + 16);
+    match seal(&kek.key, &rng, &data_key, &wrap_aad(&kek.id, credential_id)) {
+        Ok(wrapped) => payload.extend_from_slice(&wrapped),
+        Err(e) => return Err(e),
+    }
+    match seal(&key, &rng, secret.as_bytes(), &secret_aad(credential_id)) {
+        Ok(sealed) => payload.extend_from_slice(&sealed),
+        Err(e) => return Err(e),
+    }
+
+    return Ok(format!(
+        "{}:{}:{}:{}",
+        PREFIX,
+        FORMAT_VERSION,
+        kek.id,
+        base32::encode(base32::Alphabet::RFC4648 { padding: false }, &payload)
+    ));
+}
+
+// Open a sealed secret.
+// keks - The key-encryption keys, the one with the ID in the sealed secret is used.
+// credential_id - The ID of the credential the secret belongs to.
+// sealed - The sealed secret.
+#[allow(clippy::needless_late_init)]
+pub fn open_secret(
+    keks: &[KeyEncryptionKey],
+    credential_id: &String,
+    sealed: &String,
+) -> Result<String, Error> {
+    let (kek, mut payload);
+    match parse(keks, sealed) {
+        Ok((k, p)) => {
+            kek = k;
+            payload = p;
+        }
+        Err(e) => return Err(e),
+    }
+
+    let (wrapped, secret) = payload.split_at_mut(NONCE_LEN + WRAPPED_KEY_LEN);
+    let data_key;
+    match open(&kek.key, wrapped, &wrap_aad(&kek.id, credential_id)) {
+        Ok(k) => data_key = k,
+        Err(e) => return Err(e),
+    }
+
+    let key;
+    match new_key(data_key) {
+        Ok(k) => key = k,
+        Err(e) => return Err(e),
+    }
+
+    match open(&key, secret, &secret_aad(credential_id)) {
+        Ok(s) => match String::from_utf8(s.to_vec()) {
+            Ok(s) => return Ok(s),
+            Err(_) => return Err(Error::Unseal),
+        },
+        Err(e) => return Err(e),
+    }
+}
+
+// Re-wrap a sealed secret's data key under a new key-encryption key, the secret itself isn't
+// decrypted.
+// keks - The key-encryption keys, the one with the ID in the sealed secret is used.
+// new_kek - The key-encryption key to wrap the data key with.
+// credential_id - The ID of the credential the secret belongs to.
+// sealed - The sealed secret.
+#[allow(clippy::needless_late_init)]
+pub fn rewrap_secret(
+    keks: &[KeyEncryptionKey],
+    new_kek: &KeyEncryptionKey,
+    credential_id: &String,
+    sealed: &String,
+) -> Result<String, Error> {
+    let (kek, mut payload);
+    match parse(keks, sealed) {
+        Ok((k, p)) => {
+            kek = k;
+            payload = p;
+        }
+        Err(e) => return Err(e),
+    }
+
+    let (wrapped, secret) = payload.split_at_mut(NONCE_LEN + WRAPPED_KEY_LEN);
+    let data_key;
+    match open(&kek.key, wrapped, &wrap_aad(&kek.id, credential_id)) {
+        Ok(k) => data_key = k,
+        Err(e) => return Err(e),
+    }
+
+    let mut rewrapped;
+    match seal(
+        &new_kek.key,
+        &SystemRandom::new(),
+        data_key,
+        &wrap_aad(&new_kek.id, credential_id),
+    ) {
+        Ok(w) => rewrapped = w,
+        Err(e) => return Err(e),
+    }
+    rewrapped.extend_from_slice(secret);
+
+    return Ok(format!(
+        "{}:{}:{}:{}",
+        PREFIX,
+        FORMAT_VERSION,
+        new_kek.id,
+        base32::encode(base32::Alphabet::RFC4648 { padding: false }, &rewrapped)
+    ));
+}
+
+// Re-wrap every sealed secret under a new key-encryption key, stopping at the first that fails.
+// keks - The key-encryption keys the secrets are currently sealed with.
+// new_kek - The key-encryption key to wrap the data keys with.
+// sealed - The credential IDs and their sealed secrets.
+pub fn rewrap_all(
+    keks: &[KeyEncryptionKey],
+    new_kek: &KeyEncryptionKey,
+    sealed: &[(String, String)],
+) -> Result<Vec<String>, Error> {
+    let mut rewrapped = Vec::with_capacity(sealed.len());
+
+    for (id, s) in sealed {
+        match rewrap_secret(keks, new_kek, id, s) {
+            Ok(r) => rewrapped.push(r),
+            Err(e) => return Err(e),
+        }
+    }
+
+    return Ok(rewrapped);
+}
+
+// Returns the ID of the key-encryption key a secret is sealed with, such as to find the secrets
+// that still need to be re-wrapped.
+// sealed - The sealed secret.
+#[allow(clippy::ptr_arg)]
+pub fn sealed_key_id(sealed: &String) -> Result<u32, Error> {
+    let parts: Vec<&str> = sealed.splitn(4, ':').collect();
+
+    if parts.len() != 4 || parts[0] != PREFIX || parts[1] != FORMAT_VERSION.to_string() {
+        return Err(Error::Unseal);
+    }
+
+    match parts[2].parse::<u32>() {
+        Ok(id) => return Ok(id),
+        Err(_) => return Err(Error::Unseal),
+    }
+}
+
+#[allow(clippy::needless_late_init)]
+fn parse<'a>(
+    keks: &'a [KeyEncryptionKey],
+    sealed: &String,
+) -> Result<(&'a KeyEncryptionKey, Vec<u8>), Error> {
+    let id;
+    match sealed_key_id(sealed) {
+        Ok(i) => id = i,
+        Err(e) => return Err(e),
+    }
+
+    let kek;
+    match keks.iter().find(|k| k.id == id) {
+        Some(k) => kek = k,
+        None => return Err(Error::UnknownKeyVersion(id)),
+    }
+
+    let encoded = sealed.rsplit(':').next().unwrap_or("");
+    match base32::decode(base32::Alphabet::RFC4648 { padding: false }, encoded) {
+        Some(p) if p.len() >= 2 * NONCE_LEN + WRAPPED_KEY_LEN + 16 => return Ok((kek, p)),
+        _ => return Err(Error::Unseal),
+    }
+}
+
+fn new_key(key: &[u8]) -> Result<LessSafeKey, Error> {
+    match UnboundKey::new(&AES_256_GCM, key) {
+        Ok(k) => return Ok(LessSafeKey::new(k)),
+        Err(_) => return Err(Error::InvalidKeyLength(key.len())),
+    }
+}
+
+// The associated data of a wrapped data key, binding it to the format, key and credential.
+fn wrap_aad(kek_id: &u32, credential_id: &String) -> Vec<u8> {
+    return format!("{}:{}:{}:{}", PREFIX, FORMAT_VERSION, kek_id, credential_id).into_bytes();
+}
+
+// The associated data of an encrypted secret, which doesn't depend on the key-encryption key so
+// that it survives re-wrapping.
+fn secret_aad(credential_id: &String) -> Vec<u8> {
+    return format!("{}:{}:{}", PREFIX, FORMAT_VERSION, credential_id).into_bytes();
+}
+
+// Encrypt with a random nonce, returning the nonce followed by the ciphertext and tag.
+fn seal(
+    key: &LessSafeKey,
+    rng: &SystemRandom,
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let mut nonce = [0u8; NONCE_LEN];
+    if rng.fill(&mut nonce).is_err() {
+        return Err(Error::RandomFailure);
+    }
+
+    let mut in_out = plaintext.to_vec();
+    match key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::from(aad),
+        &mut in_out,
+    ) {
+        Ok(_) => {
+            let mut sealed = nonce.to_vec();
+            sealed.extend_from_slice(&in_out);
+            return Ok(sealed);
+        }
+        Err(_) => return Err(Error::Seal),
+    }
+}
+
+// Decrypt the output of seal in place, returning the plaintext.
+#[allow(clippy::needless_late_init)]
+fn open<'a>(key: &LessSafeKey, sealed: &'a mut [u8], aad: &[u8]) -> Result<&'a [u8], Error> {
+    let (nonce_bytes, in_out) = sealed.split_at_mut(NONCE_LEN);
+    let nonce;
+    match Nonce::try_assume_unique_for_key(nonce_bytes) {
+        Ok(n) => nonce = n,
+        Err(_) => return Err(Error::Unseal),
+    }
+
+    match key.open_in_place(nonce, Aad::from(aad), in_out) {
+        Ok(plaintext) => return Ok(plaintext),
+        Err(_) => return Err(Error::Unseal),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_seal_and_open() {
+        use super::*;
+        let kek = match KeyEncryptionKey::new(&1, &[3u8; 32]) {
+            Ok(k) => k,
+            Err(e) => panic!("An error occurred when creating the key: {}", e),
+        };
+        let id = String::from("user-1/phone");
+        let secret = String::from("JBSWY3DPEHPK3PXP");
+
+        let sealed = match seal_secret(&kek, &id, &secret) {
+            Ok(s) => s,
+            Err(e) => panic!("An error occurred when sealing the secret: {}", e),
+        };
+        assert!(sealed.starts_with("lotp:1:1:"));
+        assert!(!sealed.contains(secret.as_str()));
+        assert_ne!(seal_secret(&kek, &id, &secret).unwrap(), sealed);
+
+        let keks = [kek];
+        assert_eq!(open_secret(&keks, &id, &sealed), Ok(secret));
+
+        // The sealed secret is bound to the credential and can't be modified.
+        assert_eq!(
+            open_secret(&keks, &String::from("user-2/phone"), &sealed),
+            Err(Error::Unseal)
+        );
+        let mut tampered = sealed.clone();
+        let i = tampered.len() - 10;
+        let c = if &tampered[i..i + 1] == "A" { "B" } else { "A" };
+        tampered.replace_range(i..i + 1, c);
+        assert_eq!(open_secret(&keks, &id, &tampered), Err(Error::Unseal));
+        assert_eq!(
+            open_secret(&keks, &id, &String::from("lotp:1:1:AAAA")),
+            Err(Error::Unseal)
+        );
+
+        assert!(KeyEncryptionKey::new(&1, &[3u8; 16]).is_err());
+    }
+
+    #[test]
+    fn test_rewrap() {
+        use super::*;
+        let old = KeyEncryptionKey::new(&1, &[3u8; 32]).unwrap();
+        let new = KeyEncryptionKey::new(&2, &[4u8; 32]).unwrap();
+        let secrets = [
+            (String::from("a"), String::from("JBSWY3DPEHPK3PXP")),
+            (String::from("b"), String::from("GEZDGNBVGY3TQOJQ")),
+        ];
+        let sealed: Vec<(String, String)> = secrets
+            .iter()
+            .map(|(id, s)| (id.clone(), seal_secret(&old, id, s).unwrap()))
+            .collect();
+
+        let rewrapped = match rewrap_all(&[old], &new, &sealed) {
+            Ok(r) => r,
+            Err(e) => panic!("An error occurred when re-wrapping the secrets: {}", e),
+        };
+
+        let keks = [new];
+        for (i, (id, secret)) in secrets.iter().enumerate() {
+            assert_eq!(sealed_key_id(&rewrapped[i]), Ok(2));
+            assert_eq!(open_secret(&keks, id, &rewrapped[i]), Ok(secret.clone()));
+            assert_eq!(
+                open_secret(&keks, id, &sealed[i].1),
+                Err(Error::UnknownKeyVersion(1))
+            );
+        }
+    }
+}
@@ -1,34 +1,166 @@
-// Contains all the possible types of errors.
-#[derive(Clone, PartialEq)]
-pub enum ErrorType {
-    NonBase32,      // The string supplied wasn't a base-32 string.
-    InvalidCounter, // The counter was invalid when checking the unix time.
-    InvalidOffset,  // The offset when checking tokens is too large or small.
-}
+use alloc::string::String;
+use core::fmt;
 
-// Struct which is returned to indicate an error.
-#[derive(Clone, PartialEq)]
-pub struct Error {
-    err_type: ErrorType,
-    desc: String,
+// All the errors that can occur when generating or verifying codes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    // The secret supplied wasn't a base-32 string.
+    // position - The index of the first character that isn't valid base-32.
+    NonBase32 { position: usize },
+    // The secret is empty, such as a credential deserialized without its secret.
+    MissingSecret,
+    // The amount of digits isn't supported, codes must have 6, 7 or 8 digits.
+    InvalidDigits(u32),
+    // The TOTP period (duration_secs) must be at least one second.
+    InvalidPeriod(u64),
+    // The system clock is set before the UNIX epoch, so no time step could be calculated.
+    ClockBeforeEpoch,
+    // An otpauth URI could not be parsed, contains the reason.
+    InvalidUri(String),
+    // Fewer consecutive codes than required were supplied for resynchronization.
+    TooFewCodes,
+    // A lookup table would need more memory than the limit allows.
+    TableTooLarge,
+    // A credential store failed, contains the reason.
+    Storage(String),
+    // A secret with fewer than the required amount of bytes was requested.
+    SecretTooShort(usize),
+    // The system's secure random number generator failed.
+    RandomFailure,
+    // The enrollment has expired and can no longer be confirmed.
+    EnrollmentExpired,
+    // The enrollment has already been confirmed.
+    EnrollmentConfirmed,
+    // A recovery code format or hashing parameter is invalid, contains the reason.
+    InvalidFormat(String),
+    // No master key or key-encryption key with the version is known.
+    UnknownKeyVersion(u32),
+    // A key doesn't have the required length, contains the length supplied.
+    InvalidKeyLength(usize),
+    // HKDF could not derive a secret from a master key.
+    KeyDerivation,
+    // A secret or data key could not be encrypted.
+    Seal,
+    // A sealed secret is malformed, was tampered with or was sealed for another credential.
+    Unseal,
+    // An out-of-band code could not be delivered, contains the reason.
+    Delivery(String),
+    // A HOTP credential accepted a code for the largest counter and can't be used again.
+    CounterExhausted,
+    // A batch was given a different amount of codes than credentials.
+    // credentials - The amount of credentials.
+    // codes - The amount of codes.
+    BatchLength { credentials: usize, codes: usize },
+    // An HMAC backend failed, contains the reason.
+    Backend(String),
+    // An HMAC backend returned a tag shorter than the 20 bytes of HMAC-SHA-1, contains its length.
+    InvalidTag(usize),
+    // A buffer is too small to hold a code.
+    // needed - The amount of bytes the code needs.
+    // len - The length of the buffer.
+    BufferTooSmall { needed: usize, len: usize },
+    // A PKCS#11 module failed or a key could not be found, contains the reason.
+    Pkcs11(String),
 }
 
-impl Error {
-    // Creates a new error.
-    pub fn new(error_type: ErrorType, description: &'static str) -> Error {
-        return Error {
-            err_type: error_type,
-            desc: String::from(description),
-        };
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NonBase32 { position } => write!(
+                f,
+                "The secret provided is not a base-32 string, the character at {} is invalid.",
+                position
+            ),
+            Error::MissingSecret => write!(f, "No secret was provided."),
+            Error::InvalidDigits(digits) => write!(
+                f,
+                "Codes with {} digits are not supported, use 6, 7 or 8 digits.",
+                digits
+            ),
+            Error::InvalidPeriod(period) => write!(
+                f,
+                "A period of {} seconds is invalid, it must be at least one second.",
+                period
+            ),
+            Error::ClockBeforeEpoch => write!(
+                f,
+                "Could not calculate a value for the current counter, the clock is before the UNIX epoch."
+            ),
+            Error::InvalidUri(reason) => write!(f, "The otpauth URI is invalid: {}", reason),
+            Error::TooFewCodes => write!(
+                f,
+                "At least two consecutive codes are required to resynchronize a counter."
+            ),
+            Error::TableTooLarge => write!(
+                f,
+                "The requested range of counters needs more memory than allowed."
+            ),
+            Error::Storage(reason) => write!(f, "The credential store failed: {}", reason),
+            Error::SecretTooShort(bytes) => write!(
+                f,
+                "A secret of {} bytes is too short, secrets must have at least 16 bytes.",
+                bytes
+            ),
+            Error::RandomFailure => write!(f, "The secure random number generator failed."),
+            Error::EnrollmentExpired => write!(f, "The enrollment has expired."),
+            Error::EnrollmentConfirmed => write!(f, "The enrollment has already been confirmed."),
+            Error::InvalidFormat(reason) => {
+                write!(f, "The format or hashing parameters are invalid: {}", reason)
+            }
+            Error::UnknownKeyVersion(version) => {
+                write!(f, "No key with version {} is known.", version)
+            }
+            Error::InvalidKeyLength(length) => {
+                write!(f, "A key of {} bytes is invalid, keys must have 32 bytes.", length)
+            }
+            Error::KeyDerivation => write!(f, "A secret could not be derived from the master key."),
+            Error::Seal => write!(f, "The secret could not be sealed."),
+            Error::Unseal => write!(f, "The sealed secret could not be opened."),
+            Error::Delivery(reason) => write!(f, "The code could not be delivered: {}", reason),
+            Error::CounterExhausted => write!(
+                f,
+                "The HOTP counter is exhausted, the credential must be replaced."
+            ),
+            Error::BatchLength { credentials, codes } => write!(
+                f,
+                "{} codes were given for {} credentials, each credential needs one code.",
+                codes, credentials
+            ),
+            Error::Backend(reason) => write!(f, "The HMAC backend failed: {}", reason),
+            Error::InvalidTag(len) => write!(
+                f,
+                "The HMAC backend returned a tag of {} bytes, at least 20 bytes are required.",
+                len
+            ),
+            Error::BufferTooSmall { needed, len } => write!(
+                f,
+                "A buffer of {} bytes can't hold a code of {} digits.",
+                len, needed
+            ),
+            Error::Pkcs11(reason) => write!(f, "The PKCS#11 module failed: {}", reason),
+        }
     }
+}
 
-    // Returns the type of error that occurred.
-    pub fn error_type(&self) -> ErrorType {
-        return self.err_type.clone();
-    }
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_error_display() {
+        use super::*;
+        let e = Error::NonBase32 { position: 3 };
+        assert_eq!(
+            e.to_string(),
+            "The secret provided is not a base-32 string, the character at 3 is invalid."
+        );
 
-    // Returns a description of the error that occurred.
-    pub fn description(&self) -> String {
-        return self.desc.clone();
+        #[cfg(feature = "std")]
+        {
+            let boxed: Box<dyn std::error::Error> = Box::new(Error::InvalidPeriod(0));
+            assert!(boxed.to_string().contains("0 seconds"));
+        }
     }
 }
@@ -0,0 +1,96 @@
+// Salted PBKDF2 hashes of values that are only ever verified, such as recovery codes and PINs.
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::num::NonZeroU32;
+
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct SaltedHash {
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+impl SaltedHash {
+    // Hash a value with a new random salt.
+    // value - The value to hash.
+    // iterations - The amount of PBKDF2 iterations, at least one.
+    #[allow(clippy::needless_late_init)]
+    pub(crate) fn derive(value: &[u8], iterations: &u32) -> Result<SaltedHash, Error> {
+        let salt;
+        match random_salt() {
+            Ok(s) => salt = s,
+            Err(e) => return Err(e),
+        }
+
+        match derive_with_salt(value, &salt, iterations) {
+            Ok(hash) => return Ok(SaltedHash { salt, hash }),
+            Err(e) => return Err(e),
+        }
+    }
+
+    // Returns true if the value matches the hash, the hashes are compared in constant time.
+    // value - The value to check.
+    // iterations - The amount of PBKDF2 iterations the hash was derived with.
+    pub(crate) fn matches(&self, value: &[u8], iterations: &u32) -> bool {
+        match rounds_for(iterations) {
+            Ok(rounds) => {
+                return pbkdf2::verify(
+                    pbkdf2::PBKDF2_HMAC_SHA256,
+                    rounds,
+                    &self.salt,
+                    value,
+                    &self.hash,
+                )
+                .is_ok()
+            }
+            Err(_) => return false,
+        }
+    }
+}
+
+// Returns a new random salt.
+pub(crate) fn random_salt() -> Result<Vec<u8>, Error> {
+    let mut salt = vec![0u8; SALT_LEN];
+    if SystemRandom::new().fill(&mut salt).is_err() {
+        return Err(Error::RandomFailure);
+    }
+
+    return Ok(salt);
+}
+
+// Hash a value with a salt that is shared with other values, such as every code in a set.
+// value - The value to hash.
+// salt - The salt.
+// iterations - The amount of PBKDF2 iterations, at least one.
+#[allow(clippy::needless_late_init)]
+pub(crate) fn derive_with_salt(
+    value: &[u8],
+    salt: &[u8],
+    iterations: &u32,
+) -> Result<Vec<u8>, Error> {
+    let rounds;
+    match rounds_for(iterations) {
+        Ok(r) => rounds = r,
+        Err(e) => return Err(e),
+    }
+
+    let mut hash = vec![0u8; HASH_LEN];
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, rounds, salt, value, &mut hash);
+    return Ok(hash);
+}
+
+fn rounds_for(iterations: &u32) -> Result<NonZeroU32, Error> {
+    match NonZeroU32::new(*iterations) {
+        Some(r) => return Ok(r),
+        None => {
+            return Err(Error::InvalidFormat(String::from(
+                "At least one PBKDF2 iteration is required.",
+            )))
+        }
+    }
+}
@@ -1,49 +1,170 @@
-use crate::error::{Error, ErrorType};
-use ring::hmac;
+#![allow(clippy::needless_return)]
+use crate::audit::{self, Auditor};
+use crate::backend::{self, HmacKey, SoftwareKey, MAX_TAG_BYTES};
+use crate::code::Code;
+use crate::error::Error;
+use crate::outcome::{self, Outcome};
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+// The number of digits in a generated code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u32", into = "u32")
+)]
+pub enum Digits {
+    Six,
+    Seven,
+    Eight,
+}
 
-// Check a HOTP code with 6 digits as a string.
+impl TryFrom<u32> for Digits {
+    type Error = Error;
+
+    fn try_from(digits: u32) -> Result<Digits, Error> {
+        match digits {
+            6 => return Ok(Digits::Six),
+            7 => return Ok(Digits::Seven),
+            8 => return Ok(Digits::Eight),
+            _ => return Err(Error::InvalidDigits(digits)),
+        }
+    }
+}
+
+impl From<Digits> for u32 {
+    fn from(digits: Digits) -> u32 {
+        return digits.count() as u32;
+    }
+}
+
+impl Digits {
+    // Returns the amount of digits as a number.
+    pub fn count(&self) -> usize {
+        match self {
+            Digits::Six => return 6,
+            Digits::Seven => return 7,
+            Digits::Eight => return 8,
+        }
+    }
+
+    // Returns the value that a truncated HOTP value is reduced by to produce a code.
+    pub(crate) fn modulus(&self) -> u64 {
+        return 10u64.pow(self.count() as u32);
+    }
+}
+
+// The hash algorithm used to generate codes. SHA-1 is the default and the only algorithm that
+// RFC 4226 defines, SHA-256 and SHA-512 are allowed for TOTP by RFC 6238.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "UPPERCASE")
+)]
+pub enum Algorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+// The result of a successful look-ahead HOTP verification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HotpMatch {
+    pub counter: u64,              // The counter that the code matched.
+    pub next_counter: Option<u64>, // The counter to expect next, None once the counter is exhausted.
+}
+
+// Check a HOTP code against the counters within offset of the counter and return the outcome.
 // counter - the value of the hotp counter.
 // secret - The secret used to generate the hash.
 // offset - The value added and subtracted from the counter that are considered valid.
-// comparison - The code that is is to bee checked if valid.
-pub fn check_6_digit_hotp(
+// comparison - The code that is is to be checked if valid.
+// digits - The amount of digits in the code.
+// auditor - The observer the attempt is reported to, if any.
+pub fn check_hotp(
     counter: &u64,
     secret: &String,
     offset: &u64,
     comparison: &String,
-) -> Result<bool, Error> {
-    if offset == &0u64 {
-        match generate_6_digit_hotp_string(counter, secret) {
-            Ok(ref hotp) => return Ok(hotp == comparison),
-            Err(e) => return Err(e),
+    digits: &Digits,
+    auditor: Option<&Auditor>,
+) -> Result<Outcome, Error> {
+    match decode_key(secret) {
+        Ok(ref key) => {
+            return check_hotp_with_key(key, counter, offset, comparison, digits, auditor)
         }
-    } else {
-        let min;
-        let max;
+        Err(e) => return audit::report(auditor, None, Err(e), None),
+    }
+}
 
-        match counter.checked_sub(*offset) {
-            Some(m) => min = m,
-            None => min = 0,
-        }
+// Check a HOTP code like check_hotp, using a key from any HMAC backend.
+// key - The key used to generate the hash.
+// The remaining arguments are the same as check_hotp.
+pub fn check_hotp_with_key<K: HmacKey + ?Sized>(
+    key: &K,
+    counter: &u64,
+    offset: &u64,
+    comparison: &String,
+    digits: &Digits,
+    auditor: Option<&Auditor>,
+) -> Result<Outcome, Error> {
+    let result = check_window(key, counter, offset, comparison, digits);
+    return audit::report(auditor, None, result, None);
+}
 
-        match counter.checked_add(*offset) {
-            Some(m) => max = m,
-            None => max = std::u64::MAX,
-        }
+// Check a code against the counters within offset of the counter, without reporting the attempt.
+// key - The key used to generate the hash.
+#[allow(clippy::needless_late_init)]
+pub(crate) fn check_window<K: HmacKey + ?Sized>(
+    key: &K,
+    counter: &u64,
+    offset: &u64,
+    comparison: &String,
+    digits: &Digits,
+) -> Result<Outcome, Error> {
+    let code;
+    match outcome::parse_code(comparison, digits) {
+        Some(c) => code = c,
+        None => return Ok(Outcome::Malformed),
+    }
 
-        for i in min..=max {
-            match generate_6_digit_hotp_string(&i, secret) {
-                Ok(ref hotp) => {
-                    if hotp == comparison {
-                        return Ok(true);
-                    }
-                }
-                Err(e) => return Err(e),
-            }
+    let min = counter.saturating_sub(*offset);
+    let max = counter.saturating_add(*offset);
+
+    match scan_window(key, &min, &max, &code, digits) {
+        Ok(Some(i)) => {
+            return Ok(Outcome::Valid {
+                counter: i,
+                step_delta: outcome::step_delta(&i, counter),
+            })
         }
+        Ok(None) => return Ok(Outcome::Invalid),
+        Err(e) => return Err(e),
     }
+}
 
-    return Ok(false);
+// Check a HOTP code with 6 digits as a string.
+// counter - the value of the hotp counter.
+// secret - The secret used to generate the hash.
+// offset - The value added and subtracted from the counter that are considered valid.
+// comparison - The code that is is to bee checked if valid.
+pub fn check_6_digit_hotp(
+    counter: &u64,
+    secret: &String,
+    offset: &u64,
+    comparison: &String,
+) -> Result<bool, Error> {
+    match check_hotp(counter, secret, offset, comparison, &Digits::Six, None) {
+        Ok(o) => return Ok(o.is_valid()),
+        Err(e) => return Err(e),
+    }
 }
 
 // Check a HOTP code with 7 digits as a string.
@@ -57,38 +178,10 @@ pub fn check_7_digit_hotp(
     offset: &u64,
     comparison: &String,
 ) -> Result<bool, Error> {
-    if offset == &0u64 {
-        match generate_7_digit_hotp_string(counter, secret) {
-            Ok(ref hotp) => return Ok(hotp == comparison),
-            Err(e) => return Err(e),
-        }
-    } else {
-        let min;
-        let max;
-
-        match counter.checked_sub(*offset) {
-            Some(m) => min = m,
-            None => min = 0,
-        }
-
-        match counter.checked_add(*offset) {
-            Some(m) => max = m,
-            None => max = std::u64::MAX,
-        }
-
-        for i in min..=max {
-            match generate_7_digit_hotp_string(&i, secret) {
-                Ok(ref hotp) => {
-                    if hotp == comparison {
-                        return Ok(true);
-                    }
-                }
-                Err(e) => return Err(e),
-            }
-        }
+    match check_hotp(counter, secret, offset, comparison, &Digits::Seven, None) {
+        Ok(o) => return Ok(o.is_valid()),
+        Err(e) => return Err(e),
     }
-
-    return Ok(false);
 }
 
 // Check a HOTP code with 8 digits as a string.
@@ -102,128 +195,370 @@ pub fn check_8_digit_hotp(
     offset: &u64,
     comparison: &String,
 ) -> Result<bool, Error> {
-    if offset == &0u64 {
-        match generate_8_digit_hotp_string(counter, secret) {
-            Ok(ref hotp) => return Ok(hotp == comparison),
+    match check_hotp(counter, secret, offset, comparison, &Digits::Eight, None) {
+        Ok(o) => return Ok(o.is_valid()),
+        Err(e) => return Err(e),
+    }
+}
+
+// Verify a HOTP code against the counter and the counters after it, never accepting a counter
+// that has already been passed (RFC 4226 section 7.4). On success the matched counter is returned
+// along with the counter to store for the next verification.
+// counter - The next expected value of the hotp counter.
+// secret - The secret used to generate the hash.
+// look_ahead - The amount of counters after the expected counter that are considered valid.
+// comparison - The code that is is to be checked if valid.
+// digits - The amount of digits in the code.
+pub fn verify_hotp(
+    counter: &u64,
+    secret: &String,
+    look_ahead: &u64,
+    comparison: &String,
+    digits: &Digits,
+) -> Result<Option<HotpMatch>, Error> {
+    match decode_key(secret) {
+        Ok(ref key) => return verify_hotp_with_key(key, counter, look_ahead, comparison, digits),
+        Err(e) => return Err(e),
+    }
+}
+
+// Verify a HOTP code like verify_hotp, using a key from any HMAC backend.
+// key - The key used to generate the hash.
+// The remaining arguments are the same as verify_hotp.
+#[allow(clippy::needless_late_init)]
+pub fn verify_hotp_with_key<K: HmacKey + ?Sized>(
+    key: &K,
+    counter: &u64,
+    look_ahead: &u64,
+    comparison: &String,
+    digits: &Digits,
+) -> Result<Option<HotpMatch>, Error> {
+    let code;
+    match outcome::parse_code(comparison, digits) {
+        Some(c) => code = c,
+        None => return Ok(None),
+    }
+
+    let max = counter.saturating_add(*look_ahead);
+
+    match scan_window(key, counter, &max, &code, digits) {
+        Ok(Some(i)) => {
+            return Ok(Some(HotpMatch {
+                counter: i,
+                next_counter: i.checked_add(1),
+            }))
+        }
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(e),
+    }
+}
+
+// Compute the code for every counter from min to max and return the first that matches. Every
+// counter is computed and compared in constant time whether or not an earlier one matched, so the
+// time taken doesn't reveal where in the window the code matched.
+// key - The key used to generate the hash.
+// code - The parsed code that is to be checked.
+pub(crate) fn scan_window<K: HmacKey + ?Sized>(
+    key: &K,
+    min: &u64,
+    max: &u64,
+    code: &u64,
+    digits: &Digits,
+) -> Result<Option<u64>, Error> {
+    let mut matched = None;
+
+    for i in *min..=*max {
+        match truncated_hotp(key, &i) {
+            Ok(value) => {
+                if backend::codes_equal(&(value % digits.modulus()), code) && matched.is_none() {
+                    matched = Some(i);
+                }
+            }
             Err(e) => return Err(e),
         }
-    } else {
-        let min;
-        let max;
+    }
+
+    return Ok(matched);
+}
+
+// Resynchronize a HOTP counter that has drifted beyond the normal look-ahead window, using two or
+// more consecutive codes from the token (RFC 4226 section 7.4). The counter is only updated if
+// every code matches, in order, starting from a single counter within the search range.
+// counter - The stored value of the hotp counter.
+// secret - The secret used to generate the hash.
+// comparisons - The consecutive codes that were produced by the token, oldest first.
+// max_range - The amount of counters after the stored counter that are searched.
+// digits - The amount of digits in the codes.
+#[allow(clippy::needless_late_init)]
+pub fn resync_hotp(
+    counter: &u64,
+    secret: &String,
+    comparisons: &[String],
+    max_range: &u64,
+    digits: &Digits,
+) -> Result<Option<HotpMatch>, Error> {
+    if comparisons.len() < 2 {
+        return Err(Error::TooFewCodes);
+    }
+
+    let key;
+    match decode_key(secret) {
+        Ok(k) => key = k,
+        Err(e) => return Err(e),
+    }
+
+    let mut codes = Vec::with_capacity(comparisons.len());
+    for comparison in comparisons {
+        match outcome::parse_code(comparison, digits) {
+            Some(c) => codes.push(c),
+            None => return Ok(None),
+        }
+    }
+
+    // The codes of the counters from start onwards, so each counter's code is only computed once
+    // as the window slides along the range.
+    let mut window: VecDeque<u64> = VecDeque::with_capacity(codes.len());
+    let max = counter.saturating_add(*max_range);
 
-        match counter.checked_sub(*offset) {
-            Some(m) => min = m,
-            None => min = 0,
+    for start in *counter..=max {
+        let last;
+        match start.checked_add(codes.len() as u64 - 1) {
+            Some(l) => last = l,
+            None => break,
         }
 
-        match counter.checked_add(*offset) {
-            Some(m) => max = m,
-            None => max = std::u64::MAX,
+        if window.len() == codes.len() {
+            window.pop_front();
         }
 
-        for i in min..=max {
-            match generate_8_digit_hotp_string(&i, secret) {
-                Ok(ref hotp) => {
-                    if hotp == comparison {
-                        return Ok(true);
-                    }
-                }
+        while window.len() < codes.len() {
+            match truncated_hotp(&key, &(start + window.len() as u64)) {
+                Ok(value) => window.push_back(value % digits.modulus()),
                 Err(e) => return Err(e),
             }
         }
+
+        if window.iter().eq(codes.iter()) {
+            return Ok(Some(HotpMatch {
+                counter: last,
+                next_counter: last.checked_add(1),
+            }));
+        }
     }
 
-    return Ok(false);
+    return Ok(None);
 }
 
-// Generate a HOTP code with 6 digits as a number.
-// counter - the value of the hotp counter.
-// secret - The secret used to generate the hash.
-pub fn generate_6_digit_hotp(counter: &u64, secret: &String) -> Result<u64, Error> {
-    let bytes;
-    match base32::decode(base32::Alphabet::RFC4648 { padding: false }, &secret) {
-        Some(b) => bytes = b,
-        None => {
-            return Err(Error::new(
-                ErrorType::NonBase32,
-                "The secret provided is not a base-32 string.",
-            ))
-        }
+// Decode a base-32 secret into a SHA-1 key that can be used to generate codes.
+pub(crate) fn decode_key(secret: &String) -> Result<SoftwareKey, Error> {
+    return decode_key_for(secret, &Algorithm::Sha1);
+}
+
+// Decode the secret of a stored credential, which is empty when it was deserialized from a
+// redacted credential. The free functions keep accepting empty secrets as they always have.
+pub(crate) fn decode_credential_key(
+    secret: &String,
+    algorithm: &Algorithm,
+) -> Result<SoftwareKey, Error> {
+    if secret.is_empty() {
+        return Err(Error::MissingSecret);
     }
 
-    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &bytes);
+    return decode_key_for(secret, algorithm);
+}
 
-    let tag = hmac::sign(&key, &counter.to_be_bytes().to_vec());
-    let offset = tag.as_ref()[19] & 0xf;
-    let bin_code = ((tag.as_ref()[offset as usize] & 0x7f) as i32) << 24
-        | ((tag.as_ref()[(offset as usize) + 1] & 0xff) as i32) << 16
-        | ((tag.as_ref()[(offset as usize) + 2] & 0xff) as i32) << 8
-        | ((tag.as_ref()[(offset as usize) + 3] as i32) & 0xff);
+// The largest secret in bytes that is decoded on the stack, larger secrets are decoded into a
+// vector.
+const MAX_STACK_SECRET_BYTES: usize = 128;
+
+// Decode a base-32 secret into a key for the algorithm that can be used to generate codes.
+#[allow(clippy::needless_late_init)]
+pub(crate) fn decode_key_for(secret: &String, algorithm: &Algorithm) -> Result<SoftwareKey, Error> {
+    let len = decoded_len(secret);
+    let key;
+    if len <= MAX_STACK_SECRET_BYTES {
+        let mut buf = [0u8; MAX_STACK_SECRET_BYTES];
+        key =
+            decode_base32_into(secret, &mut buf).map(|_| SoftwareKey::new(algorithm, &buf[..len]));
+    } else {
+        let mut buf = vec![0u8; len];
+        key = decode_base32_into(secret, &mut buf).map(|_| SoftwareKey::new(algorithm, &buf));
+    }
 
-    return Ok(bin_code as u64 % 1000000);
+    match key {
+        Some(k) => return k,
+        None => {
+            return Err(Error::NonBase32 {
+                position: invalid_secret_position(secret),
+            })
+        }
+    }
 }
 
-// Generate a HOTP code with 7 digits as a number.
-pub fn generate_7_digit_hotp(counter: &u64, secret: &String) -> Result<u64, Error> {
-    let bytes;
-    match base32::decode(base32::Alphabet::RFC4648 { padding: false }, &secret) {
-        Some(b) => bytes = b,
+// Decode a base-32 secret into its raw bytes, such as to import it into a hardware token.
+#[cfg(feature = "pkcs11")]
+pub(crate) fn decode_secret(secret: &String) -> Result<alloc::vec::Vec<u8>, Error> {
+    if secret.is_empty() {
+        return Err(Error::MissingSecret);
+    }
+
+    let mut buf = vec![0u8; decoded_len(secret)];
+    match decode_base32_into(secret, &mut buf) {
+        Some(_) => return Ok(buf),
         None => {
-            return Err(Error::new(
-                ErrorType::NonBase32,
-                "The secret provided is not a base-32 string.",
-            ))
+            return Err(Error::NonBase32 {
+                position: invalid_secret_position(secret),
+            })
         }
     }
+}
 
-    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &bytes);
+// Returns the amount of bytes a base-32 secret decodes to, up to six trailing padding characters
+// are ignored.
+fn decoded_len(secret: &String) -> usize {
+    let bytes = secret.as_bytes();
+    let padding = bytes
+        .iter()
+        .rev()
+        .take(6)
+        .take_while(|b| **b == b'=')
+        .count();
+    return (bytes.len() - padding) * 5 / 8;
+}
 
-    let tag = hmac::sign(&key, &counter.to_be_bytes().to_vec());
-    let offset = tag.as_ref()[19] & 0xf;
-    let bin_code = ((tag.as_ref()[offset as usize] & 0x7f) as i32) << 24
-        | ((tag.as_ref()[(offset as usize) + 1] & 0xff) as i32) << 16
-        | ((tag.as_ref()[(offset as usize) + 2] & 0xff) as i32) << 8
-        | ((tag.as_ref()[(offset as usize) + 3] as i32) & 0xff);
+// Decode a base-32 secret into a buffer, in the same way as base32::decode. Returns the amount of
+// bytes decoded, or None if the secret is invalid or doesn't fit.
+fn decode_base32_into(secret: &String, buf: &mut [u8]) -> Option<usize> {
+    let bytes = secret.as_bytes();
+    let len = decoded_len(secret);
+    if len > buf.len() {
+        return None;
+    }
 
-    return Ok(bin_code as u64 % 10000000);
+    let mut bits: u32 = 0;
+    let mut pending: u32 = 0;
+    let mut written = 0;
+
+    for b in bytes {
+        let value = match b.to_ascii_uppercase() {
+            c @ b'A'..=b'Z' => c - b'A',
+            c @ b'2'..=b'7' => c - b'2' + 26,
+            b'=' => 0,
+            _ => return None,
+        };
+
+        pending = (pending << 5) | value as u32;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            if written < len {
+                buf[written] = (pending >> bits) as u8;
+                written += 1;
+            }
+            pending &= (1 << bits) - 1;
+        }
+    }
+
+    return Some(len);
 }
 
-// Generate a HOTP code with 8 digits as a number.
-pub fn generate_8_digit_hotp(counter: &u64, secret: &String) -> Result<u64, Error> {
-    let bytes;
-    match base32::decode(base32::Alphabet::RFC4648 { padding: false }, &secret) {
-        Some(b) => bytes = b,
-        None => {
-            return Err(Error::new(
-                ErrorType::NonBase32,
-                "The secret provided is not a base-32 string.",
-            ))
+// Find the index of the first character in a secret that isn't valid base-32 or padding.
+#[allow(clippy::ptr_arg)]
+fn invalid_secret_position(secret: &String) -> usize {
+    for (i, c) in secret.chars().enumerate() {
+        if !matches!(c.to_ascii_uppercase(), 'A'..='Z' | '2'..='7' | '=') {
+            return i;
         }
     }
 
-    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &bytes);
+    // Decoding only fails because of invalid characters, so this isn't reached.
+    return 0;
+}
+
+// Calculate the dynamically truncated HOTP value for a counter, before it is reduced to the
+// required amount of digits (RFC 4226 section 5.3).
+#[allow(clippy::needless_late_init)]
+pub(crate) fn truncated_hotp<K: HmacKey + ?Sized>(key: &K, counter: &u64) -> Result<u64, Error> {
+    let mut buf = [0u8; MAX_TAG_BYTES];
+    let tag;
+    match key.sign(&counter.to_be_bytes(), &mut buf) {
+        Ok(len) if (20..=MAX_TAG_BYTES).contains(&len) => tag = &buf[..len],
+        Ok(len) => return Err(Error::InvalidTag(len)),
+        Err(e) => return Err(e),
+    }
+
+    let offset = tag[tag.len() - 1] & 0xf;
+    let bin_code = ((tag[offset as usize] & 0x7f) as i32) << 24
+        | (tag[(offset as usize) + 1] as i32) << 16
+        | (tag[(offset as usize) + 2] as i32) << 8
+        | (tag[(offset as usize) + 3] as i32);
+
+    return Ok(bin_code as u64);
+}
+
+// Format a code as a string, prepending zeroes until it has the required amount of digits.
+pub(crate) fn format_code(code: &u64, digits: &Digits) -> String {
+    return format!("{:0width$}", code, width = digits.count());
+}
+
+// Generate a HOTP code with the specified amount of digits without allocating, for secrets of up
+// to 128 bytes.
+// counter - the value of the hotp counter.
+// secret - The secret used to generate the hash.
+// digits - The amount of digits in the code.
+pub fn generate_hotp_code(counter: &u64, secret: &String, digits: &Digits) -> Result<Code, Error> {
+    match decode_key(secret) {
+        Ok(ref key) => return generate_hotp_code_with_key(key, counter, digits),
+        Err(e) => return Err(e),
+    }
+}
+
+// Generate a HOTP code like generate_hotp_code, using a key from any HMAC backend.
+// key - The key used to generate the hash.
+// counter - the value of the hotp counter.
+// digits - The amount of digits in the code.
+pub fn generate_hotp_code_with_key<K: HmacKey + ?Sized>(
+    key: &K,
+    counter: &u64,
+    digits: &Digits,
+) -> Result<Code, Error> {
+    match truncated_hotp(key, counter) {
+        Ok(value) => return Ok(Code::new(&(value % digits.modulus()), digits)),
+        Err(e) => return Err(e),
+    }
+}
+
+// Generate a HOTP code with 6 digits as a number.
+// counter - the value of the hotp counter.
+// secret - The secret used to generate the hash.
+pub fn generate_6_digit_hotp(counter: &u64, secret: &String) -> Result<u64, Error> {
+    match decode_key(secret) {
+        Ok(ref key) => return truncated_hotp(key, counter).map(|v| v % 1000000),
+        Err(e) => return Err(e),
+    }
+}
 
-    let tag = hmac::sign(&key, &counter.to_be_bytes().to_vec());
-    let offset = tag.as_ref()[19] & 0xf;
-    let bin_code = ((tag.as_ref()[offset as usize] & 0x7f) as i32) << 24
-        | ((tag.as_ref()[(offset as usize) + 1] & 0xff) as i32) << 16
-        | ((tag.as_ref()[(offset as usize) + 2] & 0xff) as i32) << 8
-        | ((tag.as_ref()[(offset as usize) + 3] as i32) & 0xff);
+// Generate a HOTP code with 7 digits as a number.
+pub fn generate_7_digit_hotp(counter: &u64, secret: &String) -> Result<u64, Error> {
+    match decode_key(secret) {
+        Ok(ref key) => return truncated_hotp(key, counter).map(|v| v % 10000000),
+        Err(e) => return Err(e),
+    }
+}
 
-    return Ok(bin_code as u64 % 100000000);
+// Generate a HOTP code with 8 digits as a number.
+pub fn generate_8_digit_hotp(counter: &u64, secret: &String) -> Result<u64, Error> {
+    match decode_key(secret) {
+        Ok(ref key) => return truncated_hotp(key, counter).map(|v| v % 100000000),
+        Err(e) => return Err(e),
+    }
 }
 
 // Generate a HOTP code with 6 digits as a string.
 pub fn generate_6_digit_hotp_string(counter: &u64, secret: &String) -> Result<String, Error> {
     match generate_6_digit_hotp(counter, secret) {
-        Ok(n) => {
-            let mut string: String = format!("{}", n);
-            while string.len() < 6 {
-                string.insert(0, '0');
-            }
-
-            return Ok(string);
-        }
+        Ok(n) => return Ok(format_code(&n, &Digits::Six)),
         Err(e) => return Err(e),
     }
 }
@@ -231,14 +566,7 @@ pub fn generate_6_digit_hotp_string(counter: &u64, secret: &String) -> Result<St
 // Generate a HOTP code with 7 digits as a string.
 pub fn generate_7_digit_hotp_string(counter: &u64, secret: &String) -> Result<String, Error> {
     match generate_7_digit_hotp(counter, secret) {
-        Ok(n) => {
-            let mut string: String = format!("{}", n);
-            while string.len() < 7 {
-                string.insert(0, '0');
-            }
-
-            return Ok(string);
-        }
+        Ok(n) => return Ok(format_code(&n, &Digits::Seven)),
         Err(e) => return Err(e),
     }
 }
@@ -246,19 +574,27 @@ pub fn generate_7_digit_hotp_string(counter: &u64, secret: &String) -> Result<St
 // Generate a HOTP code with 8 digits as a string.
 pub fn generate_8_digit_hotp_string(counter: &u64, secret: &String) -> Result<String, Error> {
     match generate_8_digit_hotp(counter, secret) {
-        Ok(n) => {
-            let mut string: String = format!("{}", n);
-            // Prepend with zeroes until we reach a length of 8.
-            while string.len() < 8 {
-                string.insert(0, '0');
-            }
-
-            return Ok(string);
-        }
+        Ok(n) => return Ok(format_code(&n, &Digits::Eight)),
         Err(e) => return Err(e),
     }
 }
 
+// Generate a HOTP code with the specified amount of digits as a string.
+// counter - the value of the hotp counter.
+// secret - The secret used to generate the hash.
+// digits - The amount of digits in the code.
+pub fn generate_hotp_string(
+    counter: &u64,
+    secret: &String,
+    digits: &Digits,
+) -> Result<String, Error> {
+    match digits {
+        Digits::Six => return generate_6_digit_hotp_string(counter, secret),
+        Digits::Seven => return generate_7_digit_hotp_string(counter, secret),
+        Digits::Eight => return generate_8_digit_hotp_string(counter, secret),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -266,10 +602,7 @@ mod tests {
         use super::*;
         match generate_6_digit_hotp(&0, &String::from("abcdef234567")) {
             Ok(_) => (),
-            Err(e) => panic!(
-                "An error occurred when generating the hotp: {}",
-                e.description()
-            ),
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
         }
     }
 
@@ -278,10 +611,7 @@ mod tests {
         use super::*;
         match generate_7_digit_hotp(&0, &String::from("abcdef234567")) {
             Ok(_) => (),
-            Err(e) => panic!(
-                "An error occurred when generating the hotp: {}",
-                e.description()
-            ),
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
         }
     }
 
@@ -290,10 +620,7 @@ mod tests {
         use super::*;
         match generate_8_digit_hotp(&0, &String::from("abcdef234567")) {
             Ok(_) => (),
-            Err(e) => panic!(
-                "An error occurred when generating the hotp: {}",
-                e.description()
-            ),
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
         }
     }
 
@@ -302,10 +629,7 @@ mod tests {
         use super::*;
         match generate_6_digit_hotp_string(&0, &String::from("abcdef234567")) {
             Ok(s) => assert_eq!(s.len(), 6),
-            Err(e) => panic!(
-                "An error occurred when generating the hotp: {}",
-                e.description()
-            ),
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
         }
     }
 
@@ -314,10 +638,7 @@ mod tests {
         use super::*;
         match generate_7_digit_hotp_string(&0, &String::from("abcdef234567")) {
             Ok(s) => assert_eq!(s.len(), 7),
-            Err(e) => panic!(
-                "An error occurred when generating the hotp: {}",
-                e.description()
-            ),
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
         }
     }
 
@@ -326,10 +647,7 @@ mod tests {
         use super::*;
         match generate_8_digit_hotp_string(&0, &String::from("abcdef234567")) {
             Ok(s) => assert_eq!(s.len(), 8),
-            Err(e) => panic!(
-                "An error occurred when generating the hotp: {}",
-                e.description()
-            ),
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
         }
     }
 
@@ -340,16 +658,10 @@ mod tests {
             Ok(ref s) => {
                 match check_6_digit_hotp(&397u64, &String::from("abcdef234567"), &6u64, s) {
                     Ok(b) => assert!(b),
-                    Err(e) => panic!(
-                        "An error occurred when generating the hotp: {}",
-                        e.description()
-                    ),
+                    Err(e) => panic!("An error occurred when generating the hotp: {}", e),
                 }
             }
-            Err(e) => panic!(
-                "An error occurred when checking the hotp: {}",
-                e.description()
-            ),
+            Err(e) => panic!("An error occurred when checking the hotp: {}", e),
         }
     }
 
@@ -360,16 +672,10 @@ mod tests {
             Ok(ref s) => {
                 match check_6_digit_hotp(&397u64, &String::from("abcdef234567"), &1u64, s) {
                     Ok(b) => assert!(!b),
-                    Err(e) => panic!(
-                        "An error occurred when checking the hotp: {}",
-                        e.description()
-                    ),
+                    Err(e) => panic!("An error occurred when checking the hotp: {}", e),
                 }
             }
-            Err(e) => panic!(
-                "An error occurred when generating the hotp: {}",
-                e.description()
-            ),
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
         }
     }
 
@@ -380,16 +686,10 @@ mod tests {
             Ok(ref s) => {
                 match check_7_digit_hotp(&397u64, &String::from("abcdef234567"), &6u64, s) {
                     Ok(b) => assert!(b),
-                    Err(e) => panic!(
-                        "An error occurred when generating the hotp: {}",
-                        e.description()
-                    ),
+                    Err(e) => panic!("An error occurred when generating the hotp: {}", e),
                 }
             }
-            Err(e) => panic!(
-                "An error occurred when checking the hotp: {}",
-                e.description()
-            ),
+            Err(e) => panic!("An error occurred when checking the hotp: {}", e),
         }
     }
 
@@ -400,16 +700,10 @@ mod tests {
             Ok(ref s) => {
                 match check_7_digit_hotp(&397u64, &String::from("abcdef234567"), &1u64, s) {
                     Ok(b) => assert!(!b),
-                    Err(e) => panic!(
-                        "An error occurred when checking the hotp: {}",
-                        e.description()
-                    ),
+                    Err(e) => panic!("An error occurred when checking the hotp: {}", e),
                 }
             }
-            Err(e) => panic!(
-                "An error occurred when generating the hotp: {}",
-                e.description()
-            ),
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
         }
     }
 
@@ -420,16 +714,10 @@ mod tests {
             Ok(ref s) => {
                 match check_8_digit_hotp(&397u64, &String::from("abcdef234567"), &6u64, s) {
                     Ok(b) => assert!(b),
-                    Err(e) => panic!(
-                        "An error occurred when generating the hotp: {}",
-                        e.description()
-                    ),
+                    Err(e) => panic!("An error occurred when generating the hotp: {}", e),
                 }
             }
-            Err(e) => panic!(
-                "An error occurred when checking the hotp: {}",
-                e.description()
-            ),
+            Err(e) => panic!("An error occurred when checking the hotp: {}", e),
         }
     }
 
@@ -440,16 +728,267 @@ mod tests {
             Ok(ref s) => {
                 match check_8_digit_hotp(&397u64, &String::from("abcdef234567"), &1u64, s) {
                     Ok(b) => assert!(!b),
-                    Err(e) => panic!(
-                        "An error occurred when checking the hotp: {}",
-                        e.description()
-                    ),
+                    Err(e) => panic!("An error occurred when checking the hotp: {}", e),
                 }
             }
-            Err(e) => panic!(
-                "An error occurred when generating the hotp: {}",
-                e.description()
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_verify_hotp_look_ahead() {
+        use super::*;
+        let secret = String::from("abcdef234567");
+        match generate_hotp_string(&403u64, &secret, &Digits::Six) {
+            Ok(ref s) => match verify_hotp(&400u64, &secret, &5u64, s, &Digits::Six) {
+                Ok(m) => assert_eq!(
+                    m,
+                    Some(HotpMatch {
+                        counter: 403,
+                        next_counter: Some(404),
+                    })
+                ),
+                Err(e) => panic!("An error occurred when verifying the hotp: {}", e),
+            },
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_verify_hotp_scans_whole_window() {
+        use super::*;
+        use core::cell::Cell;
+        // Counts the HMACs computed by the key it wraps.
+        struct CountingKey {
+            key: SoftwareKey,
+            calls: Cell<u64>,
+        }
+
+        impl HmacKey for CountingKey {
+            fn sign(&self, message: &[u8], out: &mut [u8; MAX_TAG_BYTES]) -> Result<usize, Error> {
+                self.calls.set(self.calls.get() + 1);
+                return self.key.sign(message, out);
+            }
+        }
+
+        let secret = String::from("abcdef234567");
+        let key = CountingKey {
+            key: decode_key(&secret).unwrap(),
+            calls: Cell::new(0),
+        };
+
+        // A match at the first counter still computes every counter in the window.
+        let code = generate_hotp_string(&400u64, &secret, &Digits::Six).unwrap();
+        match verify_hotp_with_key(&key, &400, &5, &code, &Digits::Six) {
+            Ok(m) => assert_eq!(m.map(|m| m.counter), Some(400)),
+            Err(e) => panic!("An error occurred when verifying the hotp: {}", e),
+        }
+        assert_eq!(key.calls.get(), 6);
+    }
+
+    #[test]
+    fn test_verify_hotp_rejects_past_counter() {
+        use super::*;
+        let secret = String::from("abcdef234567");
+        match generate_hotp_string(&399u64, &secret, &Digits::Six) {
+            Ok(ref s) => match verify_hotp(&400u64, &secret, &5u64, s, &Digits::Six) {
+                Ok(m) => assert_eq!(m, None),
+                Err(e) => panic!("An error occurred when verifying the hotp: {}", e),
+            },
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_verify_hotp_saturates() {
+        use super::*;
+        let secret = String::from("abcdef234567");
+        match generate_hotp_string(&u64::MAX, &secret, &Digits::Eight) {
+            Ok(ref s) => match verify_hotp(&(u64::MAX - 1), &secret, &10u64, s, &Digits::Eight) {
+                Ok(m) => assert_eq!(
+                    m,
+                    Some(HotpMatch {
+                        counter: u64::MAX,
+                        next_counter: None,
+                    })
+                ),
+                Err(e) => panic!("An error occurred when verifying the hotp: {}", e),
+            },
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_resync_hotp() {
+        use super::*;
+        let secret = String::from("abcdef234567");
+        let mut codes = Vec::new();
+        for i in 5000u64..5003 {
+            match generate_hotp_string(&i, &secret, &Digits::Six) {
+                Ok(s) => codes.push(s),
+                Err(e) => panic!("An error occurred when generating the hotp: {}", e),
+            }
+        }
+
+        match resync_hotp(&10u64, &secret, &codes, &10000u64, &Digits::Six) {
+            Ok(m) => assert_eq!(
+                m,
+                Some(HotpMatch {
+                    counter: 5002,
+                    next_counter: Some(5003),
+                })
             ),
+            Err(e) => panic!("An error occurred when resynchronizing the hotp: {}", e),
+        }
+
+        // The codes must be consecutive.
+        codes.swap(0, 1);
+        match resync_hotp(&10u64, &secret, &codes, &10000u64, &Digits::Six) {
+            Ok(m) => assert_eq!(m, None),
+            Err(e) => panic!("An error occurred when resynchronizing the hotp: {}", e),
+        }
+
+        // A malformed code never matches.
+        codes.swap(0, 1);
+        codes[2] = String::from("12345a");
+        match resync_hotp(&10u64, &secret, &codes, &10000u64, &Digits::Six) {
+            Ok(m) => assert_eq!(m, None),
+            Err(e) => panic!("An error occurred when resynchronizing the hotp: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_resync_hotp_too_few_codes() {
+        use super::*;
+        let codes = vec![String::from("123456")];
+        match resync_hotp(
+            &0u64,
+            &String::from("abcdef234567"),
+            &codes,
+            &100u64,
+            &Digits::Six,
+        ) {
+            Ok(_) => panic!("A single code should not resynchronize the counter."),
+            Err(e) => assert_eq!(e, Error::TooFewCodes),
+        }
+    }
+
+    #[test]
+    fn test_check_hotp_outcome() {
+        use super::*;
+        let secret = String::from("abcdef234567");
+        match generate_hotp_string(&398u64, &secret, &Digits::Seven) {
+            Ok(ref s) => match check_hotp(&400u64, &secret, &3u64, s, &Digits::Seven, None) {
+                Ok(o) => assert_eq!(
+                    o,
+                    Outcome::Valid {
+                        counter: 398,
+                        step_delta: -2,
+                    }
+                ),
+                Err(e) => panic!("An error occurred when checking the hotp: {}", e),
+            },
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
+        }
+
+        match check_hotp(
+            &400u64,
+            &secret,
+            &3u64,
+            &String::from("12a456"),
+            &Digits::Six,
+            None,
+        ) {
+            Ok(o) => assert_eq!(o, Outcome::Malformed),
+            Err(e) => panic!("An error occurred when checking the hotp: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_non_base32_position() {
+        use super::*;
+        match generate_6_digit_hotp(&0, &String::from("abc1ef234567")) {
+            Ok(_) => panic!("The secret should not be valid base-32."),
+            Err(e) => assert_eq!(e, Error::NonBase32 { position: 3 }),
+        }
+
+        assert_eq!(Digits::try_from(9), Err(Error::InvalidDigits(9)));
+    }
+
+    #[test]
+    fn test_generate_hotp_code() {
+        use super::*;
+        let secret = String::from("abcdef234567");
+        match generate_hotp_code(&400u64, &secret, &Digits::Seven) {
+            Ok(c) => match generate_7_digit_hotp_string(&400u64, &secret) {
+                Ok(s) => assert_eq!(c, s),
+                Err(e) => panic!("An error occurred when generating the hotp: {}", e),
+            },
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_short_tag() {
+        use super::*;
+        struct ShortKey;
+
+        impl HmacKey for ShortKey {
+            fn sign(
+                &self,
+                _message: &[u8],
+                _out: &mut [u8; MAX_TAG_BYTES],
+            ) -> Result<usize, Error> {
+                return Ok(10);
+            }
+        }
+
+        assert_eq!(
+            generate_hotp_code_with_key(&ShortKey, &0, &Digits::Six),
+            Err(Error::InvalidTag(10))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_decode_base32_into() {
+        use super::*;
+        let long = "ABCDEFGH234567".repeat(20);
+        for secret in [
+            "abcdef234567",
+            "MZXW6YQ=",
+            "MZXW6===",
+            "A=B",
+            "GEZDGNBV",
+            &long,
+        ] {
+            let secret = String::from(secret);
+            let expected = base32::decode(base32::Alphabet::RFC4648 { padding: false }, &secret);
+            let mut buf = [0u8; 256];
+            let decoded = decode_base32_into(&secret, &mut buf).map(|len| buf[..len].to_vec());
+            assert_eq!(decoded, expected, "{}", secret);
+        }
+
+        // Secrets that don't fit are decoded into a vector instead.
+        assert_eq!(decode_base32_into(&long, &mut [0u8; 128]), None);
+        assert!(decode_key(&long).is_ok());
+        assert_eq!(
+            decode_key(&String::from("abc1")).err(),
+            Some(Error::NonBase32 { position: 3 })
+        );
+    }
+
+    #[test]
+    fn test_empty_secret() {
+        use super::*;
+        // The free functions hash with an empty key, only stored credentials require a secret.
+        match generate_6_digit_hotp_string(&0, &String::new()) {
+            Ok(code) => assert_eq!(code.len(), 6),
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
         }
+        assert_eq!(
+            decode_credential_key(&String::new(), &Algorithm::Sha1).err(),
+            Some(Error::MissingSecret)
+        );
     }
 }
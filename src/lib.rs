@@ -1,3 +1,49 @@
+// The crate is written with explicit returns, so every module allows clippy::needless_return.
+// Functions that initialise from match arms or take secrets and codes as &String allow
+// clippy::needless_late_init and clippy::ptr_arg individually.
+
+// Without the std feature only the HOTP and TOTP computation is available, with time supplied by
+// the caller.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+pub mod audit;
+pub mod backend;
+pub mod batch;
+pub mod code;
+#[cfg(all(feature = "std", feature = "ring"))]
+pub mod derive;
+pub mod drift;
+#[cfg(all(feature = "std", feature = "ring"))]
+pub mod enrollment;
+#[cfg(all(feature = "std", feature = "ring"))]
+pub mod envelope;
 pub mod error;
+#[cfg(all(feature = "std", feature = "ring"))]
+mod hash;
 pub mod hotp;
+#[cfg(feature = "std")]
+pub mod lookup;
+pub mod multi;
+#[cfg(all(feature = "std", feature = "ring"))]
+pub mod oob;
+pub mod otp;
+pub mod outcome;
+#[cfg(all(feature = "std", feature = "ring"))]
+pub mod pin;
+#[cfg(all(feature = "pkcs11", unix))]
+pub mod pkcs11;
+#[cfg(all(feature = "std", feature = "ring"))]
+pub mod recovery;
+pub mod rotation;
+#[cfg(all(feature = "std", feature = "ring"))]
+pub mod secret;
+#[cfg(feature = "std")]
+pub mod shared;
+#[cfg(feature = "std")]
+pub mod storage;
+pub mod throttle;
 pub mod totp;
+pub mod uri;
+pub mod verifier;
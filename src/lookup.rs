@@ -0,0 +1,166 @@
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use crate::hotp::{self, Digits};
+use crate::outcome;
+use std::mem;
+use std::thread;
+
+// A precomputed index from HOTP codes to the counters that produce them, for a single secret and
+// a contiguous range of counters. Building the table computes every code in the range once, in
+// parallel, after which any amount of lookups can be answered without computing a HMAC.
+// Entries are stored as a sorted list of (code, counter offset) pairs, 8 bytes per counter.
+pub struct HotpLookupTable {
+    start: u64,               // The first counter in the table.
+    digits: Digits,           // The amount of digits in the indexed codes.
+    entries: Vec<(u32, u32)>, // (code, counter - start), sorted by code and then counter.
+}
+
+impl HotpLookupTable {
+    // Build a table for the counters start..start + len.
+    // secret - The secret used to generate the hash.
+    // start - The first counter in the table.
+    // len - The amount of counters in the table, this is cut short if it would pass u64::MAX.
+    // digits - The amount of digits in the codes.
+    // max_bytes - The maximum amount of memory the table entries may use.
+    #[allow(clippy::needless_late_init)]
+    pub fn build(
+        secret: &String,
+        start: &u64,
+        len: &u32,
+        digits: &Digits,
+        max_bytes: &usize,
+    ) -> Result<HotpLookupTable, Error> {
+        let len = (u64::MAX - start).saturating_add(1).min(*len as u64) as usize;
+
+        match len.checked_mul(mem::size_of::<(u32, u32)>()) {
+            Some(bytes) if bytes <= *max_bytes => (),
+            _ => return Err(Error::TableTooLarge),
+        }
+
+        let key;
+        match hotp::decode_key(secret) {
+            Ok(k) => key = k,
+            Err(e) => return Err(e),
+        }
+
+        let threads = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        let chunk_size = len.div_ceil(threads).max(1);
+        let modulus = digits.modulus();
+        let mut entries = vec![(0u32, 0u32); len];
+
+        let computed: Result<(), Error> = thread::scope(|scope| {
+            let handles: Vec<_> = entries
+                .chunks_mut(chunk_size)
+                .enumerate()
+                .map(|(chunk_index, chunk)| {
+                    let key = &key;
+                    scope.spawn(move || {
+                        let chunk_start = chunk_index * chunk_size;
+                        for (i, entry) in chunk.iter_mut().enumerate() {
+                            let offset = (chunk_start + i) as u32;
+                            match hotp::truncated_hotp(key, &(start + offset as u64)) {
+                                Ok(value) => *entry = ((value % modulus) as u32, offset),
+                                Err(e) => return Err(e),
+                            }
+                        }
+
+                        return Ok(());
+                    })
+                })
+                .collect();
+
+            return handles.into_iter().try_for_each(|h| match h.join() {
+                Ok(r) => r,
+                Err(panic) => std::panic::resume_unwind(panic),
+            });
+        });
+
+        match computed {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+
+        entries.sort_unstable();
+
+        return Ok(HotpLookupTable {
+            start: *start,
+            digits: *digits,
+            entries,
+        });
+    }
+
+    // Returns every counter in the table that produces the code, in ascending order.
+    // comparison - The code to look up.
+    #[allow(clippy::needless_late_init, clippy::ptr_arg)]
+    pub fn find(&self, comparison: &String) -> Vec<u64> {
+        let code;
+        match outcome::parse_code(comparison, &self.digits) {
+            Some(c) => code = c as u32,
+            None => return Vec::new(),
+        }
+
+        let first = self.entries.partition_point(|e| e.0 < code);
+        return self.entries[first..]
+            .iter()
+            .take_while(|e| e.0 == code)
+            .map(|e| self.start + e.1 as u64)
+            .collect();
+    }
+
+    // Returns the first counter in the table.
+    pub fn start(&self) -> u64 {
+        return self.start;
+    }
+
+    // Returns the amount of counters in the table.
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    // Returns true if the table contains no counters.
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_lookup_table_find() {
+        use super::*;
+        let secret = String::from("abcdef234567");
+        let table = match HotpLookupTable::build(&secret, &1000, &20000, &Digits::Six, &(1 << 20)) {
+            Ok(t) => t,
+            Err(e) => panic!("An error occurred when building the table: {}", e),
+        };
+
+        assert_eq!(table.len(), 20000);
+
+        for counter in [1000u64, 12345, 20999] {
+            match hotp::generate_6_digit_hotp_string(&counter, &secret) {
+                Ok(ref s) => assert!(table.find(s).contains(&counter)),
+                Err(e) => panic!("An error occurred when generating the hotp: {}", e),
+            }
+        }
+
+        assert!(table.find(&String::from("12345")).is_empty());
+        assert!(table.find(&String::from("١٢٣٤٥٦")).is_empty());
+    }
+
+    #[test]
+    fn test_lookup_table_memory_limit() {
+        use super::*;
+        match HotpLookupTable::build(
+            &String::from("abcdef234567"),
+            &0,
+            &1000,
+            &Digits::Six,
+            &1024,
+        ) {
+            Ok(_) => panic!("The table should exceed the memory limit."),
+            Err(e) => assert_eq!(e, Error::TableTooLarge),
+        }
+    }
+}
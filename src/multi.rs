@@ -0,0 +1,232 @@
+// Verifying a code against every credential a user has enrolled, such as a phone and a hardware
+// token. A credential that fails, such as one with a malformed secret, is reported and skipped so
+// it doesn't lock the user out of their other devices. Every credential is checked and every
+// counter in each credential's window is computed, whichever credential matches, so the time taken
+// doesn't reveal which device the code came from.
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use crate::otp::{Credential, OtpVerifier};
+use crate::outcome::Outcome;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// The credential that accepted a code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceMatch {
+    pub index: usize,           // The position of the credential in the verifier.
+    pub credential: Credential, // The credential's updated state, which should be persisted.
+}
+
+// The result of verifying a code against all of a user's credentials.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MultiOutcome {
+    pub outcome: Outcome,             // The combined outcome.
+    pub matched: Option<DeviceMatch>, // The credential that accepted the code, if any.
+    pub errors: Vec<(usize, Error)>,  // The position and error of each credential that failed.
+}
+
+// A user's credentials, which may differ in type, algorithm, digits and period.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MultiVerifier {
+    credentials: Vec<Credential>,
+}
+
+impl MultiVerifier {
+    // Creates a verifier for a set of credentials.
+    // credentials - The user's credentials.
+    pub fn new(credentials: &[Credential]) -> MultiVerifier {
+        return MultiVerifier {
+            credentials: credentials.to_vec(),
+        };
+    }
+
+    // Returns the credentials, including any state updated by accepted codes.
+    pub fn credentials(&self) -> &[Credential] {
+        return &self.credentials;
+    }
+
+    // Adds a credential, such as a newly enrolled device.
+    // credential - The credential to add.
+    pub fn push(&mut self, credential: &Credential) {
+        self.credentials.push(credential.clone());
+    }
+
+    // Check a code against every credential at the time now. If more than one credential accepts
+    // the code the first is used, only the matching credential's state is updated. Otherwise the
+    // outcome is Replayed if any credential had already accepted the code, Malformed if the code
+    // couldn't be a code for any of the credentials, and Invalid otherwise. Credentials that fail
+    // are recorded in errors and don't affect the outcome, an error is only returned when every
+    // credential failed.
+    // comparison - The code that is is to be checked if valid.
+    // now - The current unix time in seconds.
+    pub fn verify_all_at(&mut self, comparison: &String, now: &u64) -> Result<MultiOutcome, Error> {
+        let mut matched: Option<DeviceMatch> = None;
+        let mut valid = Outcome::Invalid;
+        let mut replayed = false;
+        let mut well_formed = false;
+        let mut errors = Vec::new();
+
+        // Each credential is verified on a copy so that one that isn't used keeps its state.
+        for (index, credential) in self.credentials.iter().enumerate() {
+            let mut candidate = credential.clone();
+
+            match candidate.verify_at(comparison, now) {
+                Ok(o @ Outcome::Valid { .. }) => {
+                    well_formed = true;
+                    if matched.is_none() {
+                        valid = o;
+                        matched = Some(DeviceMatch {
+                            index,
+                            credential: candidate,
+                        });
+                    }
+                }
+                Ok(Outcome::Replayed) => {
+                    well_formed = true;
+                    replayed = true;
+                }
+                Ok(Outcome::Malformed) => (),
+                Ok(_) => well_formed = true,
+                Err(e) => errors.push((index, e)),
+            }
+        }
+
+        if !self.credentials.is_empty() && errors.len() == self.credentials.len() {
+            return Err(errors.swap_remove(0).1);
+        }
+
+        let outcome;
+        if let Some(ref m) = matched {
+            self.credentials[m.index] = m.credential.clone();
+            outcome = valid;
+        } else if replayed {
+            outcome = Outcome::Replayed;
+        } else if well_formed {
+            outcome = Outcome::Invalid;
+        } else {
+            outcome = Outcome::Malformed;
+        }
+
+        return Ok(MultiOutcome {
+            outcome,
+            matched,
+            errors,
+        });
+    }
+}
+
+impl OtpVerifier for MultiVerifier {
+    fn verify_at(&mut self, comparison: &String, now: &u64) -> Result<Outcome, Error> {
+        match self.verify_all_at(comparison, now) {
+            Ok(m) => return Ok(m.outcome),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_multi_verifier() {
+        use super::*;
+        use crate::hotp::{Algorithm, Digits};
+        use crate::otp::{Hotp, OtpGenerator, Totp};
+        let mut phone = Totp::new(&String::from("abcdef234567abcdef234567"));
+        phone.algorithm = Algorithm::Sha256;
+        phone.digits = Digits::Eight;
+        phone.period = 60;
+        let token = Hotp::new(&String::from("zyxwvu765432zyxwvu765432"), &20);
+
+        let mut verifier = MultiVerifier::new(&[
+            Credential::Totp(phone.clone()),
+            Credential::Hotp(token.clone()),
+        ]);
+
+        let code = token.generate_at(&1000).unwrap();
+        match verifier.verify_all_at(&code, &1000) {
+            Ok(m) => {
+                assert!(m.outcome.is_valid());
+                let matched = m.matched.unwrap();
+                assert_eq!(matched.index, 1);
+                match matched.credential {
+                    Credential::Hotp(h) => assert_eq!(h.counter, 21),
+                    _ => panic!("The wrong credential matched."),
+                }
+            }
+            Err(e) => panic!("An error occurred when verifying the code: {}", e),
+        }
+
+        // The phone's state is untouched.
+        assert_eq!(verifier.credentials()[0], Credential::Totp(phone.clone()));
+
+        let code = phone.generate_at(&1000).unwrap();
+        match verifier.verify_all_at(&code, &1000) {
+            Ok(m) => assert_eq!(m.matched.map(|m| m.index), Some(0)),
+            Err(e) => panic!("An error occurred when verifying the code: {}", e),
+        }
+
+        match verifier.verify_all_at(&code, &1000) {
+            Ok(m) => {
+                assert_eq!(m.outcome, Outcome::Replayed);
+                assert!(m.matched.is_none());
+            }
+            Err(e) => panic!("An error occurred when verifying the code: {}", e),
+        }
+
+        match verifier.verify_at(&String::from("12345"), &1000) {
+            Ok(o) => assert_eq!(o, Outcome::Malformed),
+            Err(e) => panic!("An error occurred when verifying the code: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_multi_verifier_skips_failed_credentials() {
+        use super::*;
+        use crate::otp::{OtpGenerator, Totp};
+        let phone = Totp::new(&String::from("abcdef234567abcdef234567"));
+        let broken = Totp::new(&String::new());
+        let mut verifier = MultiVerifier::new(&[
+            Credential::Totp(broken.clone()),
+            Credential::Totp(phone.clone()),
+        ]);
+
+        let code = phone.generate_at(&1000).unwrap();
+        match verifier.verify_all_at(&code, &1000) {
+            Ok(m) => {
+                assert_eq!(m.matched.map(|m| m.index), Some(1));
+                assert_eq!(m.errors, vec![(0, Error::MissingSecret)]);
+            }
+            Err(e) => panic!("An error occurred when verifying the code: {}", e),
+        }
+
+        // An error is only returned when no credential could be checked.
+        let mut verifier = MultiVerifier::new(&[Credential::Totp(broken)]);
+        assert_eq!(
+            verifier.verify_all_at(&code, &1000),
+            Err(Error::MissingSecret)
+        );
+    }
+
+    #[test]
+    fn test_multi_verifier_checks_every_credential() {
+        use super::*;
+        use crate::otp::{Hotp, OtpGenerator, Totp};
+        let phone = Totp::new(&String::from("abcdef234567abcdef234567"));
+        let token = Hotp::new(&String::from("zyxwvu765432zyxwvu765432"), &20);
+        let mut verifier = MultiVerifier::new(&[
+            Credential::Totp(phone.clone()),
+            Credential::Hotp(token),
+            Credential::Totp(Totp::new(&String::new())),
+        ]);
+
+        // The first credential matches, yet the last is still checked and its error reported.
+        let code = phone.generate_at(&1000).unwrap();
+        match verifier.verify_all_at(&code, &1000) {
+            Ok(m) => {
+                assert_eq!(m.matched.map(|m| m.index), Some(0));
+                assert_eq!(m.errors, vec![(2, Error::MissingSecret)]);
+            }
+            Err(e) => panic!("An error occurred when verifying the code: {}", e),
+        }
+    }
+}
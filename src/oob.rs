@@ -0,0 +1,271 @@
+// Out-of-band codes sent to the user by email or SMS. Unlike HOTP and TOTP codes they are purely
+// random, only a salted hash of the code is kept along with its expiry and the attempts made.
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use crate::hash::SaltedHash;
+use crate::secret;
+use ring::rand::SystemRandom;
+use std::sync::Mutex;
+
+// How out-of-band codes are issued and checked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OobConfig {
+    pub alphabet: String,  // The characters a code is made of, each only once.
+    pub length: usize,     // The amount of characters in a code.
+    pub ttl_secs: u64,     // The amount of seconds a code is valid for.
+    pub max_attempts: u32, // The amount of wrong codes allowed for each code.
+    pub iterations: u32,   // The amount of PBKDF2 iterations used to hash the code.
+}
+
+impl OobConfig {
+    // Creates the default configuration, 6 digit codes valid for ten minutes with five attempts.
+    pub fn new() -> OobConfig {
+        return OobConfig {
+            alphabet: String::from("0123456789"),
+            length: 6,
+            ttl_secs: 600,
+            max_attempts: 5,
+            iterations: 10_000,
+        };
+    }
+}
+
+impl Default for OobConfig {
+    fn default() -> OobConfig {
+        return OobConfig::new();
+    }
+}
+
+// The result of checking an out-of-band code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OobOutcome {
+    Valid,           // The code matched and has been consumed.
+    Invalid,         // The code didn't match, the attempt was counted.
+    Expired,         // The code's lifetime has passed.
+    Consumed,        // The code has already been used.
+    TooManyAttempts, // Too many wrong codes were entered, a new code must be issued.
+    Malformed,       // The code has the wrong length or characters, it wasn't counted.
+}
+
+// Delivers out-of-band codes to users.
+pub trait CodeSender {
+    // Send a code to a destination such as an email address or phone number.
+    // destination - Where to send the code.
+    // code - The code, it should only ever be passed to the delivery service.
+    #[allow(clippy::ptr_arg)]
+    fn send(&self, destination: &String, code: &String) -> Result<(), Error>;
+}
+
+// A sender that keeps sent codes in memory, for tests.
+#[derive(Debug, Default)]
+pub struct MemorySender {
+    sent: Mutex<Vec<(String, String)>>, // The destinations and codes sent.
+}
+
+impl MemorySender {
+    // Creates a sender that hasn't sent anything.
+    pub fn new() -> MemorySender {
+        return MemorySender {
+            sent: Mutex::new(Vec::new()),
+        };
+    }
+
+    // Returns the last code sent to a destination.
+    // destination - Where the code was sent.
+    pub fn last_code(&self, destination: &String) -> Option<String> {
+        let sent = self.sent.lock().unwrap_or_else(|e| e.into_inner());
+        return sent
+            .iter()
+            .rev()
+            .find(|(d, _)| d == destination)
+            .map(|(_, c)| c.clone());
+    }
+}
+
+impl CodeSender for MemorySender {
+    fn send(&self, destination: &String, code: &String) -> Result<(), Error> {
+        let mut sent = self.sent.lock().unwrap_or_else(|e| e.into_inner());
+        sent.push((destination.clone(), code.clone()));
+        return Ok(());
+    }
+}
+
+// An issued out-of-band code, this should be stored until it is used or expires.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OobChallenge {
+    hash: SaltedHash,  // The hash of the code.
+    alphabet: String,  // The characters the code is made of.
+    length: usize,     // The amount of characters in the code.
+    iterations: u32,   // The amount of PBKDF2 iterations used to hash the code.
+    expires_at: u64,   // The unix time at which the code expires.
+    attempts: u32,     // The amount of wrong codes entered.
+    max_attempts: u32, // The amount of wrong codes allowed.
+    consumed: bool,    // Whether the code has been used.
+}
+
+impl OobChallenge {
+    // Issue a new code and send it to the user.
+    // config - How the code is issued.
+    // sender - Delivers the code.
+    // destination - Where to send the code, such as an email address or phone number.
+    // now - The current unix time in seconds.
+    #[allow(clippy::needless_late_init)]
+    pub fn issue(
+        config: &OobConfig,
+        sender: &dyn CodeSender,
+        destination: &String,
+        now: &u64,
+    ) -> Result<OobChallenge, Error> {
+        match secret::validate_alphabet(&config.alphabet) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+
+        if config.length == 0 {
+            return Err(Error::InvalidFormat(String::from(
+                "Codes must have at least one character.",
+            )));
+        }
+
+        let mut code = String::with_capacity(config.length);
+        match secret::push_random_chars(
+            &SystemRandom::new(),
+            &config.alphabet,
+            &config.length,
+            &mut code,
+        ) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+
+        let hash;
+        match SaltedHash::derive(code.as_bytes(), &config.iterations) {
+            Ok(h) => hash = h,
+            Err(e) => return Err(e),
+        }
+
+        match sender.send(destination, &code) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+
+        return Ok(OobChallenge {
+            hash,
+            alphabet: config.alphabet.clone(),
+            length: config.length,
+            iterations: config.iterations,
+            expires_at: now.saturating_add(config.ttl_secs),
+            attempts: 0,
+            max_attempts: config.max_attempts,
+            consumed: false,
+        });
+    }
+
+    // Returns the unix time at which the code expires.
+    pub fn expires_at(&self) -> u64 {
+        return self.expires_at;
+    }
+
+    // Returns the amount of wrong codes entered.
+    pub fn attempts(&self) -> u32 {
+        return self.attempts;
+    }
+
+    // Verify a code, consuming it if it matches. The challenge should be persisted after every
+    // verification so that the attempts are counted.
+    // comparison - The code entered by the user.
+    // now - The current unix time in seconds.
+    pub fn verify(&mut self, comparison: &String, now: &u64) -> OobOutcome {
+        if self.consumed {
+            return OobOutcome::Consumed;
+        } else if *now >= self.expires_at {
+            return OobOutcome::Expired;
+        } else if self.attempts >= self.max_attempts {
+            return OobOutcome::TooManyAttempts;
+        } else if comparison.len() != self.length
+            || !comparison.chars().all(|c| self.alphabet.contains(c))
+        {
+            return OobOutcome::Malformed;
+        }
+
+        if self.hash.matches(comparison.as_bytes(), &self.iterations) {
+            self.consumed = true;
+            return OobOutcome::Valid;
+        }
+
+        self.attempts += 1;
+        return OobOutcome::Invalid;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_oob_challenge() {
+        use super::*;
+        let sender = MemorySender::new();
+        let email = String::from("alice@example.com");
+        let config = OobConfig {
+            iterations: 10,
+            ..OobConfig::new()
+        };
+
+        let mut challenge = match OobChallenge::issue(&config, &sender, &email, &1000) {
+            Ok(c) => c,
+            Err(e) => panic!("An error occurred when issuing the code: {}", e),
+        };
+        let code = sender.last_code(&email).unwrap();
+        assert_eq!(code.len(), 6);
+        assert!(code.chars().all(|c| c.is_ascii_digit()));
+
+        let wrong: String = code
+            .chars()
+            .map(|c| char::from(b'0' + (c as u8 - b'0' + 1) % 10))
+            .collect();
+        assert_eq!(challenge.verify(&wrong, &1000), OobOutcome::Invalid);
+        assert_eq!(
+            challenge.verify(&String::from("12ab"), &1000),
+            OobOutcome::Malformed
+        );
+        assert_eq!(challenge.attempts(), 1);
+
+        assert_eq!(challenge.verify(&code, &1599), OobOutcome::Valid);
+        assert_eq!(challenge.verify(&code, &1599), OobOutcome::Consumed);
+    }
+
+    #[test]
+    fn test_oob_limits() {
+        use super::*;
+        let sender = MemorySender::new();
+        let phone = String::from("+15550100");
+        let config = OobConfig {
+            alphabet: String::from("ABCDEFGHJKLMNPQRSTUVWXYZ"),
+            length: 8,
+            max_attempts: 2,
+            iterations: 10,
+            ..OobConfig::new()
+        };
+
+        let mut challenge = OobChallenge::issue(&config, &sender, &phone, &1000).unwrap();
+        let code = sender.last_code(&phone).unwrap();
+        assert_eq!(code.len(), 8);
+        assert_eq!(challenge.verify(&code, &1600), OobOutcome::Expired);
+
+        let mut locked = challenge.clone();
+        let wrong = String::from("AAAAAAAA");
+        for _ in 0..2 {
+            assert_eq!(locked.verify(&wrong, &1000), OobOutcome::Invalid);
+        }
+        assert_eq!(locked.verify(&code, &1000), OobOutcome::TooManyAttempts);
+        assert_eq!(challenge.verify(&code, &1000), OobOutcome::Valid);
+
+        let config = OobConfig {
+            length: 0,
+            ..OobConfig::new()
+        };
+        assert!(OobChallenge::issue(&config, &sender, &phone, &1000).is_err());
+    }
+}
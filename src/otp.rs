@@ -0,0 +1,638 @@
+#![allow(clippy::needless_return)]
+use crate::audit::{self, Auditor};
+use crate::code::Code;
+use crate::error::Error;
+use crate::hotp::{self, Algorithm, Digits};
+use crate::outcome::{self, Outcome};
+use crate::throttle::{AttemptState, ThrottlePolicy};
+use crate::totp;
+use crate::verifier::Verifier;
+use alloc::string::{String, ToString};
+use core::fmt;
+
+// The amount of counters after the expected counter that a Hotp accepts by default.
+pub const DEFAULT_LOOK_AHEAD: u64 = 10;
+// The period in seconds of a Totp by default.
+pub const DEFAULT_PERIOD: u64 = 30;
+// The amount of time steps before and after the current one that a Totp accepts by default.
+pub const DEFAULT_SKEW: u16 = 1;
+
+// Generates the code that a credential currently expects.
+pub trait OtpGenerator {
+    // Generate the code that is valid at the time now.
+    // now - The current unix time in seconds, credentials that don't use time ignore it.
+    fn generate_at(&self, now: &u64) -> Result<String, Error>;
+
+    // Generate the code that is currently valid.
+    #[cfg(feature = "std")]
+    fn generate(&self) -> Result<String, Error> {
+        match totp::unix_time() {
+            Ok(ref now) => return self.generate_at(now),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Verifies submitted codes, updating the credential's state when a code is accepted. The
+// credential should be persisted after every verification.
+pub trait OtpVerifier {
+    // Verify a code at the time now.
+    // comparison - The code that is is to be checked if valid.
+    // now - The current unix time in seconds, credentials that don't use time ignore it.
+    #[allow(clippy::ptr_arg)]
+    fn verify_at(&mut self, comparison: &String, now: &u64) -> Result<Outcome, Error>;
+
+    // Verify a code at the current time.
+    // comparison - The code that is is to be checked if valid.
+    #[cfg(feature = "std")]
+    fn verify(&mut self, comparison: &String) -> Result<Outcome, Error> {
+        match totp::unix_time() {
+            Ok(ref now) => return self.verify_at(comparison, now),
+            Err(e) => return Err(e),
+        }
+    }
+
+    // Verify a code at the time now like verify_at, reporting the attempt to the auditor whatever
+    // the outcome.
+    // comparison - The code that is is to be checked if valid.
+    // now - The current unix time in seconds.
+    // auditor - The observer the attempt is reported to.
+    #[allow(clippy::ptr_arg)]
+    fn verify_audited_at(
+        &mut self,
+        comparison: &String,
+        now: &u64,
+        auditor: &Auditor,
+    ) -> Result<Outcome, Error> {
+        let result = self.verify_at(comparison, now);
+        return audit::report(Some(auditor), Some(*now), result, self.attempts());
+    }
+
+    // Returns the failed attempts that are kept with the verifier, for audit events.
+    fn attempts(&self) -> Option<AttemptState> {
+        return None;
+    }
+}
+
+// A credential that can both generate and verify codes. This is implemented for every type that
+// implements OtpGenerator and OtpVerifier, so credentials of mixed types can be kept together as
+// Box<dyn Otp>.
+pub trait Otp: OtpGenerator + OtpVerifier {}
+
+impl<T: OtpGenerator + OtpVerifier> Otp for T {}
+
+// A HOTP (RFC 4226) credential. Accepted codes advance the counter, so codes for past counters
+// are never accepted.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Hotp {
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "String::is_empty")
+    )]
+    pub secret: String, // The base-32 secret.
+    pub digits: Digits,       // The amount of digits in a code.
+    pub algorithm: Algorithm, // The hash algorithm used to generate codes.
+    pub counter: u64,         // The next expected counter.
+    pub look_ahead: u64,      // The amount of counters after the expected one that are accepted.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub exhausted: bool, // Whether a code for u64::MAX was accepted, so no counter is left.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub attempts: AttemptState, // The failed attempts of throttled verifications.
+}
+
+impl Hotp {
+    // Creates a 6 digit SHA-1 HOTP credential with the default look-ahead.
+    // secret - The base-32 secret.
+    // counter - The next expected counter.
+    #[allow(clippy::ptr_arg)]
+    pub fn new(secret: &String, counter: &u64) -> Hotp {
+        return Hotp {
+            secret: secret.clone(),
+            digits: Digits::Six,
+            algorithm: Algorithm::Sha1,
+            counter: *counter,
+            look_ahead: DEFAULT_LOOK_AHEAD,
+            exhausted: false,
+            attempts: AttemptState::new(),
+        };
+    }
+
+    // Generate the code for the next expected counter without allocating.
+    pub fn code(&self) -> Result<Code, Error> {
+        if self.exhausted {
+            return Err(Error::CounterExhausted);
+        }
+
+        match hotp::decode_credential_key(&self.secret, &self.algorithm) {
+            Ok(ref key) => {
+                return hotp::generate_hotp_code_with_key(key, &self.counter, &self.digits)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// The secret is never printed, so credentials can be logged safely.
+impl fmt::Debug for Hotp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("Hotp")
+            .field("secret", &"<redacted>")
+            .field("digits", &self.digits)
+            .field("algorithm", &self.algorithm)
+            .field("counter", &self.counter)
+            .field("look_ahead", &self.look_ahead)
+            .field("exhausted", &self.exhausted)
+            .field("attempts", &self.attempts)
+            .finish();
+    }
+}
+
+impl OtpGenerator for Hotp {
+    // Generate the code for the next expected counter.
+    fn generate_at(&self, _now: &u64) -> Result<String, Error> {
+        match self.code() {
+            Ok(c) => return Ok(c.to_string()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl OtpVerifier for Hotp {
+    // Verify a code against the expected counter and the look-ahead window after it. Once a code
+    // for u64::MAX is accepted the counter can't advance, so every later attempt fails with
+    // Error::CounterExhausted and the credential should be replaced.
+    #[allow(clippy::needless_late_init)]
+    fn verify_at(&mut self, comparison: &String, _now: &u64) -> Result<Outcome, Error> {
+        if self.exhausted {
+            return Err(Error::CounterExhausted);
+        }
+
+        let key;
+        match hotp::decode_credential_key(&self.secret, &self.algorithm) {
+            Ok(k) => key = k,
+            Err(e) => return Err(e),
+        }
+
+        if !outcome::is_well_formed(comparison, &self.digits) {
+            return Ok(Outcome::Malformed);
+        }
+
+        match hotp::verify_hotp_with_key(
+            &key,
+            &self.counter,
+            &self.look_ahead,
+            comparison,
+            &self.digits,
+        ) {
+            Ok(Some(m)) => {
+                let step_delta = outcome::step_delta(&m.counter, &self.counter);
+                match m.next_counter {
+                    Some(c) => self.counter = c,
+                    None => {
+                        self.counter = m.counter;
+                        self.exhausted = true;
+                    }
+                }
+                return Ok(Outcome::Valid {
+                    counter: m.counter,
+                    step_delta,
+                });
+            }
+            Ok(None) => return Ok(Outcome::Invalid),
+            Err(e) => return Err(e),
+        }
+    }
+
+    fn attempts(&self) -> Option<AttemptState> {
+        return Some(self.attempts);
+    }
+}
+
+// A TOTP (RFC 6238) credential. The verifier records the last accepted time step, so a code is
+// only accepted once, and tracks the clock drift of the device.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Totp {
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "String::is_empty")
+    )]
+    pub secret: String, // The base-32 secret.
+    pub digits: Digits,       // The amount of digits in a code.
+    pub algorithm: Algorithm, // The hash algorithm used to generate codes.
+    pub period: u64,          // The amount of seconds that each code is valid for.
+    pub skew: u16,            // The amount of time steps either side of the current one accepted.
+    pub verifier: Verifier,   // The replay and drift state.
+}
+
+impl Totp {
+    // Creates a 6 digit SHA-1 TOTP credential with the default period and skew.
+    // secret - The base-32 secret.
+    #[allow(clippy::ptr_arg)]
+    pub fn new(secret: &String) -> Totp {
+        return Totp {
+            secret: secret.clone(),
+            digits: Digits::Six,
+            algorithm: Algorithm::Sha1,
+            period: DEFAULT_PERIOD,
+            skew: DEFAULT_SKEW,
+            verifier: Verifier::new(),
+        };
+    }
+
+    // Generate the code at the time now without allocating.
+    // now - The unix time in seconds.
+    #[allow(clippy::needless_late_init)]
+    pub fn code_at(&self, now: &u64) -> Result<Code, Error> {
+        let counter;
+        match totp::counter_at(now, &self.period) {
+            Ok(c) => counter = c,
+            Err(e) => return Err(e),
+        }
+
+        match hotp::decode_credential_key(&self.secret, &self.algorithm) {
+            Ok(ref key) => return hotp::generate_hotp_code_with_key(key, &counter, &self.digits),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// The secret is never printed, so credentials can be logged safely.
+impl fmt::Debug for Totp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("Totp")
+            .field("secret", &"<redacted>")
+            .field("digits", &self.digits)
+            .field("algorithm", &self.algorithm)
+            .field("period", &self.period)
+            .field("skew", &self.skew)
+            .field("verifier", &self.verifier)
+            .finish();
+    }
+}
+
+impl OtpGenerator for Totp {
+    fn generate_at(&self, now: &u64) -> Result<String, Error> {
+        match self.code_at(now) {
+            Ok(c) => return Ok(c.to_string()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl OtpVerifier for Totp {
+    #[allow(clippy::needless_late_init)]
+    fn verify_at(&mut self, comparison: &String, now: &u64) -> Result<Outcome, Error> {
+        let counter;
+        match totp::counter_at(now, &self.period) {
+            Ok(c) => counter = c,
+            Err(e) => return Err(e),
+        }
+
+        match hotp::decode_credential_key(&self.secret, &self.algorithm) {
+            Ok(ref key) => {
+                return self.verifier.verify_step(
+                    key,
+                    &counter,
+                    &self.skew,
+                    comparison,
+                    &self.digits,
+                )
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    fn attempts(&self) -> Option<AttemptState> {
+        return Some(self.verifier.attempts());
+    }
+}
+
+// A credential of any of the supported schemes, for storing credentials of mixed types together.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type", rename_all = "lowercase")
+)]
+pub enum Credential {
+    Hotp(Hotp),
+    Totp(Totp),
+}
+
+impl Credential {
+    // Returns the amount of digits in the credential's codes.
+    pub fn digits(&self) -> Digits {
+        match self {
+            Credential::Hotp(c) => return c.digits,
+            Credential::Totp(c) => return c.digits,
+        }
+    }
+
+    // Verify a code at the time now, refusing every attempt while the credential is locked out.
+    // The failed attempts are kept in the credential, so the credential should be persisted after
+    // every attempt, not only accepted ones.
+    // policy - The policy that decides whether the credential is locked after a failure.
+    // comparison - The code that is is to be checked if valid.
+    // now - The current unix time in seconds.
+    // auditor - The observer the attempt is reported to, with the lockout state after it.
+    #[allow(clippy::ptr_arg)]
+    pub fn verify_throttled_at(
+        &mut self,
+        policy: &dyn ThrottlePolicy,
+        comparison: &String,
+        now: &u64,
+        auditor: Option<&Auditor>,
+    ) -> Result<Outcome, Error> {
+        let result = self.throttled(policy, comparison, now);
+        return audit::report(auditor, Some(*now), result, self.attempts());
+    }
+
+    #[allow(clippy::needless_late_init, clippy::ptr_arg)]
+    fn throttled(
+        &mut self,
+        policy: &dyn ThrottlePolicy,
+        comparison: &String,
+        now: &u64,
+    ) -> Result<Outcome, Error> {
+        if let Some(until) = self.attempts().and_then(|a| a.locked_until(now)) {
+            return Ok(Outcome::LockedOut { until });
+        }
+
+        let outcome;
+        match self.verify_at(comparison, now) {
+            Ok(o) => outcome = o,
+            Err(e) => return Err(e),
+        }
+
+        match self {
+            Credential::Hotp(c) => c.attempts.record_outcome(policy, now, &outcome),
+            Credential::Totp(c) => return Ok(c.verifier.record_attempt(policy, now, outcome)),
+        }
+
+        return Ok(outcome);
+    }
+
+    // Generate the credential's code at the time now without allocating.
+    // now - The unix time in seconds, it is ignored by HOTP credentials.
+    pub fn code_at(&self, now: &u64) -> Result<Code, Error> {
+        match self {
+            Credential::Hotp(c) => return c.code(),
+            Credential::Totp(c) => return c.code_at(now),
+        }
+    }
+}
+
+impl OtpGenerator for Credential {
+    fn generate_at(&self, now: &u64) -> Result<String, Error> {
+        match self {
+            Credential::Hotp(c) => return c.generate_at(now),
+            Credential::Totp(c) => return c.generate_at(now),
+        }
+    }
+}
+
+impl OtpVerifier for Credential {
+    fn verify_at(&mut self, comparison: &String, now: &u64) -> Result<Outcome, Error> {
+        match self {
+            Credential::Hotp(c) => return c.verify_at(comparison, now),
+            Credential::Totp(c) => return c.verify_at(comparison, now),
+        }
+    }
+
+    fn attempts(&self) -> Option<AttemptState> {
+        match self {
+            Credential::Hotp(c) => return c.attempts(),
+            Credential::Totp(c) => return c.attempts(),
+        }
+    }
+}
+
+// Serializes a credential without its secret, such as to return it from an API or to log it.
+// Deserializing the output gives a credential with an empty secret, which fails with
+// Error::MissingSecret until the secret is set. Credentials serialized directly always include
+// their secret.
+#[cfg(feature = "serde")]
+#[derive(Clone, Copy, Debug)]
+pub struct Redacted<'a, T>(pub &'a T);
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Redacted<'_, Hotp> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut hotp = self.0.clone();
+        hotp.secret = String::new();
+        return hotp.serialize(serializer);
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Redacted<'_, Totp> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut totp = self.0.clone();
+        totp.secret = String::new();
+        return totp.serialize(serializer);
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Redacted<'_, Credential> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut credential = self.0.clone();
+        match credential {
+            Credential::Hotp(ref mut c) => c.secret = String::new(),
+            Credential::Totp(ref mut c) => c.secret = String::new(),
+        }
+
+        return credential.serialize(serializer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_totp_rfc6238_vectors() {
+        use super::*;
+        let vectors = [
+            (
+                "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ",
+                Algorithm::Sha1,
+                "94287082",
+            ),
+            (
+                "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA====",
+                Algorithm::Sha256,
+                "46119246",
+            ),
+            (
+                "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNA=",
+                Algorithm::Sha512,
+                "90693936",
+            ),
+        ];
+
+        for (secret, algorithm, expected) in vectors {
+            let mut totp = Totp::new(&String::from(secret));
+            totp.digits = Digits::Eight;
+            totp.algorithm = algorithm;
+
+            match totp.generate_at(&59) {
+                Ok(code) => assert_eq!(code, expected),
+                Err(e) => panic!("An error occurred when generating the totp: {}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_mixed_credentials() {
+        use super::*;
+        let secret = String::from("abcdef234567abcdef234567");
+        let mut credentials: Vec<Box<dyn Otp>> = vec![
+            Box::new(Hotp::new(&secret, &5)),
+            Box::new(Totp::new(&secret)),
+        ];
+
+        for credential in credentials.iter_mut() {
+            let code = match credential.generate_at(&1000) {
+                Ok(c) => c,
+                Err(e) => panic!("An error occurred when generating the code: {}", e),
+            };
+
+            match credential.verify_at(&code, &1000) {
+                Ok(o) => assert!(o.is_valid()),
+                Err(e) => panic!("An error occurred when verifying the code: {}", e),
+            }
+
+            // The same code is never accepted twice.
+            match credential.verify_at(&code, &1000) {
+                Ok(o) => assert!(!o.is_valid()),
+                Err(e) => panic!("An error occurred when verifying the code: {}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_hotp_exhausted() {
+        use super::*;
+        let mut hotp = Hotp::new(&String::from("abcdef234567abcdef234567"), &u64::MAX);
+        let code = match hotp.generate_at(&0) {
+            Ok(c) => c,
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
+        };
+
+        match hotp.verify_at(&code, &0) {
+            Ok(o) => assert_eq!(
+                o,
+                Outcome::Valid {
+                    counter: u64::MAX,
+                    step_delta: 0,
+                }
+            ),
+            Err(e) => panic!("An error occurred when verifying the hotp: {}", e),
+        }
+        assert!(hotp.exhausted);
+
+        // The code for u64::MAX can't be replayed and no further codes are generated.
+        assert_eq!(hotp.verify_at(&code, &0), Err(Error::CounterExhausted));
+        assert_eq!(hotp.generate_at(&0), Err(Error::CounterExhausted));
+    }
+
+    #[test]
+    fn test_credential_throttled() {
+        use super::*;
+        use crate::throttle::FixedLockout;
+        let policy = FixedLockout {
+            max_failures: 2,
+            lockout_secs: 60,
+        };
+        let secret = String::from("abcdef234567abcdef234567");
+
+        for mut credential in [
+            Credential::Hotp(Hotp::new(&secret, &5)),
+            Credential::Totp(Totp::new(&secret)),
+        ] {
+            let code = match credential.generate_at(&1000) {
+                Ok(c) => c,
+                Err(e) => panic!("An error occurred when generating the code: {}", e),
+            };
+
+            for _ in 0..2 {
+                match credential.verify_throttled_at(&policy, &String::from("000000"), &1000, None)
+                {
+                    Ok(o) => assert_eq!(o, Outcome::Invalid),
+                    Err(e) => panic!("An error occurred when verifying the code: {}", e),
+                }
+            }
+            assert_eq!(credential.attempts().unwrap().failures(), 2);
+
+            // The lockout is kept in the credential, so even the right code is refused.
+            match credential.verify_throttled_at(&policy, &code, &1000, None) {
+                Ok(o) => assert_eq!(o, Outcome::LockedOut { until: 1060 }),
+                Err(e) => panic!("An error occurred when verifying the code: {}", e),
+            }
+
+            let code = match credential.generate_at(&1060) {
+                Ok(c) => c,
+                Err(e) => panic!("An error occurred when generating the code: {}", e),
+            };
+            match credential.verify_throttled_at(&policy, &code, &1060, None) {
+                Ok(o) => assert!(o.is_valid()),
+                Err(e) => panic!("An error occurred when verifying the code: {}", e),
+            }
+            assert_eq!(credential.attempts().unwrap().failures(), 0);
+        }
+    }
+
+    #[test]
+    fn test_debug_redacts_secret() {
+        use super::*;
+        let secret = String::from("abcdef234567abcdef234567");
+        let hotp = Credential::Hotp(Hotp::new(&secret, &5));
+        let totp = Credential::Totp(Totp::new(&secret));
+
+        for debug in [format!("{:?}", hotp), format!("{:#?}", totp)] {
+            assert!(!debug.contains(&secret));
+            assert!(debug.contains("<redacted>"));
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        use super::*;
+        let mut totp = Totp::new(&String::from("abcdef234567abcdef234567"));
+        totp.digits = Digits::Eight;
+        totp.algorithm = Algorithm::Sha256;
+
+        let code = match totp.generate_at(&1000) {
+            Ok(c) => c,
+            Err(e) => panic!("An error occurred when generating the totp: {}", e),
+        };
+        match totp.verify_at(&code, &1000) {
+            Ok(o) => assert!(o.is_valid()),
+            Err(e) => panic!("An error occurred when verifying the totp: {}", e),
+        }
+
+        let json = serde_json::to_string(&totp).unwrap();
+        assert!(json.contains("\"digits\":8"));
+        assert!(json.contains("\"algorithm\":\"SHA256\""));
+
+        let restored: Totp = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.verifier, totp.verifier);
+
+        assert_eq!(restored, totp);
+
+        // Only a Redacted credential is serialized without its secret.
+        let json = serde_json::to_string(&Redacted(&Credential::Totp(totp))).unwrap();
+        assert!(!json.contains("abcdef234567abcdef234567"));
+        assert!(json.contains("\"type\":\"totp\""));
+
+        let restored: Credential = serde_json::from_str(&json).unwrap();
+        match restored.generate_at(&1000) {
+            Ok(_) => panic!("A credential without a secret should not generate codes."),
+            Err(e) => assert_eq!(e, Error::MissingSecret),
+        }
+    }
+}
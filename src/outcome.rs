@@ -0,0 +1,62 @@
+#![allow(clippy::needless_return)]
+use crate::hotp::Digits;
+use alloc::string::String;
+
+// The outcome of verifying a code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Outcome {
+    // The code matched.
+    // counter - The HOTP counter or TOTP time step that the code matched.
+    // step_delta - The matched counter minus the expected counter or current time step.
+    Valid { counter: u64, step_delta: i64 },
+    // The code did not match any counter in the window.
+    Invalid,
+    // The code matched, but only at or before the last accepted counter.
+    Replayed,
+    // The credential is locked until the unix time until, the code wasn't checked.
+    LockedOut { until: u64 },
+    // The code isn't made up of the expected amount of digits, the code wasn't checked.
+    Malformed,
+}
+
+impl Outcome {
+    // Returns true if the code was accepted.
+    pub fn is_valid(&self) -> bool {
+        return matches!(self, Outcome::Valid { .. });
+    }
+}
+
+// Returns the difference between a matched counter and the expected counter, saturating at the
+// bounds of an i64.
+pub(crate) fn step_delta(matched: &u64, expected: &u64) -> i64 {
+    let delta = *matched as i128 - *expected as i128;
+    return delta.clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+}
+
+// Returns true if a submitted code is made up of exactly the expected amount of ASCII digits.
+#[allow(clippy::ptr_arg)]
+pub(crate) fn is_well_formed(comparison: &String, digits: &Digits) -> bool {
+    return comparison.len() == digits.count() && comparison.bytes().all(|b| b.is_ascii_digit());
+}
+
+// Parses a submitted code into a number, returning None if it isn't well formed.
+pub(crate) fn parse_code(comparison: &String, digits: &Digits) -> Option<u64> {
+    if !is_well_formed(comparison, digits) {
+        return None;
+    }
+
+    return comparison.parse::<u64>().ok();
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_is_well_formed() {
+        use super::*;
+        assert!(is_well_formed(&String::from("012345"), &Digits::Six));
+        assert!(!is_well_formed(&String::from("012345"), &Digits::Eight));
+        assert!(!is_well_formed(&String::from("01234a"), &Digits::Six));
+        assert!(!is_well_formed(&String::from("١٢٣٤٥٦"), &Digits::Six));
+    }
+}
@@ -0,0 +1,167 @@
+// Verifying a PIN followed by a code entered in a single password field, as used by VPN and RADIUS
+// logins. The PIN is only kept as a salted PBKDF2 hash.
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use crate::hash::SaltedHash;
+use crate::otp::{Credential, OtpVerifier};
+use crate::outcome::Outcome;
+use crate::throttle::AttemptState;
+
+// The amount of PBKDF2 iterations used to hash a PIN by default.
+pub const DEFAULT_PIN_ITERATIONS: u32 = 100_000;
+
+// The salted hash of a PIN.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PinHash {
+    iterations: u32,  // The amount of PBKDF2 iterations used to hash the PIN.
+    hash: SaltedHash, // The hash of the PIN.
+}
+
+impl PinHash {
+    // Hash a PIN with a new random salt.
+    // pin - The PIN, it can't be empty.
+    // iterations - The amount of PBKDF2 iterations, at least one.
+    pub fn new(pin: &String, iterations: &u32) -> Result<PinHash, Error> {
+        if pin.is_empty() {
+            return Err(Error::InvalidFormat(String::from(
+                "The PIN can't be empty.",
+            )));
+        }
+
+        match SaltedHash::derive(pin.as_bytes(), iterations) {
+            Ok(hash) => {
+                return Ok(PinHash {
+                    iterations: *iterations,
+                    hash,
+                })
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    // Returns true if the PIN matches the hash.
+    // pin - The PIN to check.
+    pub fn verify(&self, pin: &String) -> bool {
+        return self.hash.matches(pin.as_bytes(), &self.iterations);
+    }
+}
+
+// A credential protected by a PIN, the password is the PIN followed by a code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PinOtp {
+    pub pin: PinHash,           // The hash of the PIN.
+    pub credential: Credential, // The credential the code is checked against.
+}
+
+impl PinOtp {
+    // Creates a combined verifier.
+    // pin - The hash of the PIN.
+    // credential - The credential the code is checked against.
+    pub fn new(pin: &PinHash, credential: &Credential) -> PinOtp {
+        return PinOtp {
+            pin: pin.clone(),
+            credential: credential.clone(),
+        };
+    }
+}
+
+impl OtpVerifier for PinOtp {
+    // Verify a password made of the PIN followed by a code with the credential's amount of digits.
+    // Both parts are always checked and the outcome is Invalid if either is wrong, a replayed code
+    // is also reported as Invalid so that the outcome never shows the PIN was correct. The
+    // credential's state is only updated when both parts are accepted. The outcome is Malformed if
+    // the password is too short or doesn't end with a code, which reveals nothing about the PIN.
+    #[allow(clippy::needless_late_init)]
+    fn verify_at(&mut self, comparison: &String, now: &u64) -> Result<Outcome, Error> {
+        let digits = self.credential.digits().count();
+        let length = comparison.chars().count();
+
+        if length <= digits {
+            return Ok(Outcome::Malformed);
+        }
+
+        let split;
+        match comparison.char_indices().nth(length - digits) {
+            Some((i, _)) => split = i,
+            None => return Ok(Outcome::Malformed),
+        }
+
+        let (pin, code) = comparison.split_at(split);
+        let mut candidate = self.credential.clone();
+        let outcome;
+        match candidate.verify_at(&String::from(code), now) {
+            Ok(Outcome::Malformed) => return Ok(Outcome::Malformed),
+            Ok(o) => outcome = o,
+            Err(e) => return Err(e),
+        }
+
+        // The PIN is checked after the code whatever the code's outcome, so every well formed
+        // password takes the same work.
+        let pin_valid = self.pin.verify(&String::from(pin));
+
+        if pin_valid && outcome.is_valid() {
+            self.credential = candidate;
+            return Ok(outcome);
+        }
+
+        return Ok(Outcome::Invalid);
+    }
+
+    fn attempts(&self) -> Option<AttemptState> {
+        return self.credential.attempts();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_pin_otp() {
+        use super::*;
+        use crate::otp::{OtpGenerator, Totp};
+        let totp = Totp::new(&String::from("abcdef234567abcdef234567"));
+        let pin = match PinHash::new(&String::from("4821"), &10) {
+            Ok(p) => p,
+            Err(e) => panic!("An error occurred when hashing the pin: {}", e),
+        };
+        let mut verifier = PinOtp::new(&pin, &Credential::Totp(totp.clone()));
+
+        let code = totp.generate_at(&1000).unwrap();
+        let wrong_pin = format!("4822{}", code);
+        let correct = format!("4821{}", code);
+
+        for (password, expected) in [
+            (wrong_pin.clone(), Outcome::Invalid),
+            (String::from("82100"), Outcome::Malformed),
+            (String::from("48210000x"), Outcome::Malformed),
+        ] {
+            match verifier.verify_at(&password, &1000) {
+                Ok(o) => assert_eq!(o, expected),
+                Err(e) => panic!("An error occurred when verifying the password: {}", e),
+            }
+        }
+
+        // A wrong PIN doesn't consume the code.
+        match verifier.verify_at(&correct, &1000) {
+            Ok(o) => assert!(o.is_valid()),
+            Err(e) => panic!("An error occurred when verifying the password: {}", e),
+        }
+
+        // A replayed code with the correct PIN looks the same as any other failure.
+        match verifier.verify_at(&correct, &1000) {
+            Ok(o) => assert_eq!(o, Outcome::Invalid),
+            Err(e) => panic!("An error occurred when verifying the password: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_pin_hash() {
+        use super::*;
+        let pin = PinHash::new(&String::from("1234"), &10).unwrap();
+        assert!(pin.verify(&String::from("1234")));
+        assert!(!pin.verify(&String::from("12345")));
+        assert!(PinHash::new(&String::new(), &10).is_err());
+        assert!(PinHash::new(&String::from("1234"), &0).is_err());
+    }
+}
@@ -0,0 +1,626 @@
+// An HMAC backend that computes HMACs inside an HSM through a PKCS#11 module, such as SoftHSMv2.
+// Secrets are imported once, as sensitive keys that can't be extracted, and are then only referred
+// to by their handle. Truncation and formatting of codes still happen in lotp, so Pkcs11Key can be
+// passed to any of the _with_key functions.
+#![allow(clippy::needless_return)]
+use crate::backend::{HmacKey, MAX_TAG_BYTES};
+use crate::error::Error;
+use crate::hotp::{self, Algorithm};
+use libc::{c_ulong, c_void};
+use std::ffi::CString;
+use std::fmt;
+use std::ptr;
+use std::sync::Mutex;
+
+// The PKCS#11 types and constants used, from the v2.40 headers.
+type CkUlong = c_ulong;
+type CkRv = CkUlong;
+type CkSessionHandle = CkUlong;
+type CkObjectHandle = CkUlong;
+
+const CKR_OK: CkRv = 0x0;
+const CKR_USER_ALREADY_LOGGED_IN: CkRv = 0x100;
+const CKR_CRYPTOKI_ALREADY_INITIALIZED: CkRv = 0x191;
+const CKF_RW_SESSION: CkUlong = 0x2;
+const CKF_SERIAL_SESSION: CkUlong = 0x4;
+const CKF_OS_LOCKING_OK: CkUlong = 0x2;
+const CKU_USER: CkUlong = 1;
+const CKO_SECRET_KEY: CkUlong = 0x4;
+const CKK_GENERIC_SECRET: CkUlong = 0x10;
+const CKA_CLASS: CkUlong = 0x0;
+const CKA_TOKEN: CkUlong = 0x1;
+const CKA_PRIVATE: CkUlong = 0x2;
+const CKA_LABEL: CkUlong = 0x3;
+const CKA_VALUE: CkUlong = 0x11;
+const CKA_KEY_TYPE: CkUlong = 0x100;
+const CKA_SENSITIVE: CkUlong = 0x103;
+const CKA_SIGN: CkUlong = 0x108;
+const CKA_EXTRACTABLE: CkUlong = 0x162;
+const CKM_SHA_1_HMAC: CkUlong = 0x221;
+const CKM_SHA256_HMAC: CkUlong = 0x251;
+const CKM_SHA512_HMAC: CkUlong = 0x271;
+const CK_TRUE: u8 = 1;
+const CK_FALSE: u8 = 0;
+
+#[repr(C)]
+struct CkInitializeArgs {
+    create_mutex: *const c_void,
+    destroy_mutex: *const c_void,
+    lock_mutex: *const c_void,
+    unlock_mutex: *const c_void,
+    flags: CkUlong,
+    reserved: *mut c_void,
+}
+
+#[repr(C)]
+struct CkAttribute {
+    kind: CkUlong,
+    value: *const c_void,
+    value_len: CkUlong,
+}
+
+#[repr(C)]
+struct CkMechanism {
+    mechanism: CkUlong,
+    parameter: *const c_void,
+    parameter_len: CkUlong,
+}
+
+#[repr(C)]
+struct CkTokenInfo {
+    label: [u8; 32],
+    manufacturer_id: [u8; 32],
+    model: [u8; 16],
+    serial_number: [u8; 16],
+    flags: CkUlong,
+    counts: [CkUlong; 10],
+    hardware_version: [u8; 2],
+    firmware_version: [u8; 2],
+    utc_time: [u8; 16],
+}
+
+// The start of CK_FUNCTION_LIST, up to the last function used. The list is only ever read through
+// the pointer returned by the module, so the functions after C_Sign don't need to be declared.
+#[repr(C)]
+struct CkFunctionList {
+    version: [u8; 2],
+    initialize: unsafe extern "C" fn(*const CkInitializeArgs) -> CkRv,
+    finalize: unsafe extern "C" fn(*mut c_void) -> CkRv,
+    _info: [*const c_void; 2],
+    get_slot_list: unsafe extern "C" fn(u8, *mut CkUlong, *mut CkUlong) -> CkRv,
+    _slot_info: *const c_void,
+    get_token_info: unsafe extern "C" fn(CkUlong, *mut CkTokenInfo) -> CkRv,
+    _token: [*const c_void; 5],
+    open_session: unsafe extern "C" fn(
+        CkUlong,
+        CkUlong,
+        *mut c_void,
+        *const c_void,
+        *mut CkSessionHandle,
+    ) -> CkRv,
+    close_session: unsafe extern "C" fn(CkSessionHandle) -> CkRv,
+    _session: [*const c_void; 4],
+    login: unsafe extern "C" fn(CkSessionHandle, CkUlong, *const u8, CkUlong) -> CkRv,
+    _logout: *const c_void,
+    create_object: unsafe extern "C" fn(
+        CkSessionHandle,
+        *const CkAttribute,
+        CkUlong,
+        *mut CkObjectHandle,
+    ) -> CkRv,
+    _copy_object: *const c_void,
+    destroy_object: unsafe extern "C" fn(CkSessionHandle, CkObjectHandle) -> CkRv,
+    _object: [*const c_void; 3],
+    find_objects_init: unsafe extern "C" fn(CkSessionHandle, *const CkAttribute, CkUlong) -> CkRv,
+    find_objects:
+        unsafe extern "C" fn(CkSessionHandle, *mut CkObjectHandle, CkUlong, *mut CkUlong) -> CkRv,
+    find_objects_final: unsafe extern "C" fn(CkSessionHandle) -> CkRv,
+    _crypt: [*const c_void; 13],
+    sign_init: unsafe extern "C" fn(CkSessionHandle, *const CkMechanism, CkObjectHandle) -> CkRv,
+    sign: unsafe extern "C" fn(CkSessionHandle, *const u8, CkUlong, *mut u8, *mut CkUlong) -> CkRv,
+}
+
+// Returns the error for a PKCS#11 function that failed.
+// function - The name of the function, included in the error.
+// rv - The value returned by the function.
+fn failure(function: &str, rv: CkRv) -> Error {
+    return Error::Pkcs11(format!("{} returned {:#010x}.", function, rv));
+}
+
+// Returns an error unless a PKCS#11 function succeeded.
+fn check(function: &str, rv: CkRv) -> Result<(), Error> {
+    if rv == CKR_OK {
+        return Ok(());
+    }
+
+    return Err(failure(function, rv));
+}
+
+// Returns an attribute that refers to a value, which must outlive the attribute.
+fn attribute<T>(kind: CkUlong, value: &T) -> CkAttribute {
+    return CkAttribute {
+        kind,
+        value: value as *const T as *const c_void,
+        value_len: std::mem::size_of::<T>() as CkUlong,
+    };
+}
+
+// Returns an attribute that refers to bytes, which must outlive the attribute.
+fn bytes_attribute(kind: CkUlong, value: &[u8]) -> CkAttribute {
+    return CkAttribute {
+        kind,
+        value: value.as_ptr() as *const c_void,
+        value_len: value.len() as CkUlong,
+    };
+}
+
+// A loaded and initialized PKCS#11 module.
+pub struct Pkcs11Module {
+    library: *mut c_void,             // The handle returned by dlopen.
+    functions: *const CkFunctionList, // The module's function list.
+    finalize: bool,                   // Whether this module initialized the library.
+}
+
+// The module is initialized with CKF_OS_LOCKING_OK, so it may be called from any thread.
+unsafe impl Send for Pkcs11Module {}
+unsafe impl Sync for Pkcs11Module {}
+
+impl Pkcs11Module {
+    // Load and initialize a PKCS#11 module.
+    // path - The path of the module's shared library, such as /usr/lib/softhsm/libsofthsm2.so.
+    #[allow(clippy::needless_late_init)]
+    pub fn load(path: &String) -> Result<Pkcs11Module, Error> {
+        let c_path;
+        match CString::new(path.as_str()) {
+            Ok(p) => c_path = p,
+            Err(_) => return Err(Error::Pkcs11(String::from("The path contains a nul byte."))),
+        }
+
+        // SAFETY: Loading the library runs its initializers, the caller chooses the module.
+        let library = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if library.is_null() {
+            return Err(Error::Pkcs11(format!("{} could not be loaded.", path)));
+        }
+
+        // SAFETY: The symbol name is nul terminated and the library handle is valid.
+        let symbol = unsafe { libc::dlsym(library, c"C_GetFunctionList".as_ptr()) };
+        if symbol.is_null() {
+            // SAFETY: The handle is valid and isn't used again.
+            unsafe { libc::dlclose(library) };
+            return Err(Error::Pkcs11(format!("{} isn't a PKCS#11 module.", path)));
+        }
+
+        let mut module = Pkcs11Module {
+            library,
+            functions: ptr::null(),
+            finalize: false,
+        };
+
+        // SAFETY: C_GetFunctionList has this signature in every PKCS#11 version.
+        let get_function_list = unsafe {
+            std::mem::transmute::<
+                *mut c_void,
+                unsafe extern "C" fn(*mut *const CkFunctionList) -> CkRv,
+            >(symbol)
+        };
+        match check("C_GetFunctionList", unsafe {
+            get_function_list(&mut module.functions)
+        }) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+
+        if module.functions.is_null() {
+            return Err(Error::Pkcs11(String::from(
+                "C_GetFunctionList returned no functions.",
+            )));
+        }
+
+        let args = CkInitializeArgs {
+            create_mutex: ptr::null(),
+            destroy_mutex: ptr::null(),
+            lock_mutex: ptr::null(),
+            unlock_mutex: ptr::null(),
+            flags: CKF_OS_LOCKING_OK,
+            reserved: ptr::null_mut(),
+        };
+
+        // The library may already have been initialized by another module in the process, in
+        // which case it is left for that module to finalize.
+        match unsafe { (module.list().initialize)(&args) } {
+            CKR_OK => module.finalize = true,
+            CKR_CRYPTOKI_ALREADY_INITIALIZED => (),
+            rv => return Err(failure("C_Initialize", rv)),
+        }
+
+        return Ok(module);
+    }
+
+    fn list(&self) -> &CkFunctionList {
+        // SAFETY: The pointer was checked when the module was loaded and lives as long as the
+        // library is loaded.
+        return unsafe { &*self.functions };
+    }
+
+    // Returns the slot of the token with a label, or None if no token has the label.
+    // label - The token's label, as given to softhsm2-util --init-token.
+    #[allow(clippy::ptr_arg)]
+    pub fn slot_with_token(&self, label: &String) -> Result<Option<u64>, Error> {
+        let mut count: CkUlong = 0;
+        match check("C_GetSlotList", unsafe {
+            (self.list().get_slot_list)(CK_TRUE, ptr::null_mut(), &mut count)
+        }) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+
+        let mut slots = vec![0 as CkUlong; count as usize];
+        match check("C_GetSlotList", unsafe {
+            (self.list().get_slot_list)(CK_TRUE, slots.as_mut_ptr(), &mut count)
+        }) {
+            Ok(_) => slots.truncate(count as usize),
+            Err(e) => return Err(e),
+        }
+
+        for slot in slots {
+            // SAFETY: CK_TOKEN_INFO is plain data, so all zeroes is a valid value.
+            let mut info: CkTokenInfo = unsafe { std::mem::zeroed() };
+            match check("C_GetTokenInfo", unsafe {
+                (self.list().get_token_info)(slot, &mut info)
+            }) {
+                Ok(_) => (),
+                Err(e) => return Err(e),
+            }
+
+            // Labels are padded with spaces to 32 bytes.
+            if String::from_utf8_lossy(&info.label).trim_end() == label.as_str() {
+                // CK_ULONG is only 32 bits on some platforms.
+                #[allow(clippy::unnecessary_cast)]
+                return Ok(Some(slot as u64));
+            }
+        }
+
+        return Ok(None);
+    }
+
+    // Open a session with a token and log in as the user.
+    // slot - The slot of the token.
+    // pin - The user PIN of the token.
+    #[allow(clippy::ptr_arg)]
+    pub fn open_session(&self, slot: &u64, pin: &String) -> Result<Pkcs11Session<'_>, Error> {
+        let mut handle: CkSessionHandle = 0;
+        match check("C_OpenSession", unsafe {
+            (self.list().open_session)(
+                *slot as CkUlong,
+                CKF_SERIAL_SESSION | CKF_RW_SESSION,
+                ptr::null_mut(),
+                ptr::null(),
+                &mut handle,
+            )
+        }) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+
+        let session = Pkcs11Session {
+            module: self,
+            handle,
+            lock: Mutex::new(()),
+        };
+
+        // Sessions share the login state of the token.
+        match unsafe { (self.list().login)(handle, CKU_USER, pin.as_ptr(), pin.len() as CkUlong) } {
+            CKR_OK | CKR_USER_ALREADY_LOGGED_IN => return Ok(session),
+            rv => return Err(failure("C_Login", rv)),
+        }
+    }
+}
+
+impl Drop for Pkcs11Module {
+    fn drop(&mut self) {
+        // SAFETY: Sessions borrow the module, so none are open and nothing uses the library after
+        // it is closed.
+        unsafe {
+            if self.finalize {
+                (self.list().finalize)(ptr::null_mut());
+            }
+
+            libc::dlclose(self.library);
+        }
+    }
+}
+
+impl fmt::Debug for Pkcs11Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.debug_struct("Pkcs11Module").finish_non_exhaustive();
+    }
+}
+
+// A logged in session with a token. Operations on a session are serialized, open a session per
+// thread for concurrent use.
+pub struct Pkcs11Session<'a> {
+    module: &'a Pkcs11Module, // The module the session was opened with.
+    handle: CkSessionHandle,  // The session handle.
+    lock: Mutex<()>,          // Held for the length of each operation.
+}
+
+impl Pkcs11Session<'_> {
+    // Import a secret into the token as a sensitive HMAC key that can't be extracted. This is the
+    // only time lotp sees the secret, such as when it is enrolled.
+    // label - The label to store the key under.
+    // secret - The base-32 secret.
+    // algorithm - The hash algorithm the key is used with.
+    #[allow(clippy::needless_late_init)]
+    pub fn import_secret(
+        &self,
+        label: &String,
+        secret: &String,
+        algorithm: &Algorithm,
+    ) -> Result<Pkcs11Key<'_>, Error> {
+        let value;
+        match hotp::decode_secret(secret) {
+            Ok(v) => value = v,
+            Err(e) => return Err(e),
+        }
+
+        let template = [
+            attribute(CKA_CLASS, &CKO_SECRET_KEY),
+            attribute(CKA_KEY_TYPE, &CKK_GENERIC_SECRET),
+            attribute(CKA_TOKEN, &CK_TRUE),
+            attribute(CKA_PRIVATE, &CK_TRUE),
+            attribute(CKA_SENSITIVE, &CK_TRUE),
+            attribute(CKA_EXTRACTABLE, &CK_FALSE),
+            attribute(CKA_SIGN, &CK_TRUE),
+            bytes_attribute(CKA_LABEL, label.as_bytes()),
+            bytes_attribute(CKA_VALUE, &value),
+        ];
+
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut handle: CkObjectHandle = 0;
+        match check("C_CreateObject", unsafe {
+            (self.module.list().create_object)(
+                self.handle,
+                template.as_ptr(),
+                template.len() as CkUlong,
+                &mut handle,
+            )
+        }) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+
+        return Ok(Pkcs11Key {
+            session: self,
+            handle,
+            algorithm: *algorithm,
+        });
+    }
+
+    // Find an HMAC key by its label.
+    // label - The label the key is stored under.
+    // algorithm - The hash algorithm the key is used with.
+    pub fn find_key(
+        &self,
+        label: &String,
+        algorithm: &Algorithm,
+    ) -> Result<Option<Pkcs11Key<'_>>, Error> {
+        let template = [
+            attribute(CKA_CLASS, &CKO_SECRET_KEY),
+            bytes_attribute(CKA_LABEL, label.as_bytes()),
+        ];
+
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let list = self.module.list();
+        match check("C_FindObjectsInit", unsafe {
+            (list.find_objects_init)(self.handle, template.as_ptr(), template.len() as CkUlong)
+        }) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+
+        let mut handle: CkObjectHandle = 0;
+        let mut count: CkUlong = 0;
+        let found = check("C_FindObjects", unsafe {
+            (list.find_objects)(self.handle, &mut handle, 1, &mut count)
+        });
+
+        // The search is always finished, even if it failed.
+        let finished = check("C_FindObjectsFinal", unsafe {
+            (list.find_objects_final)(self.handle)
+        });
+
+        match found.and(finished) {
+            Ok(_) if count == 0 => return Ok(None),
+            Ok(_) => {
+                return Ok(Some(Pkcs11Key {
+                    session: self,
+                    handle,
+                    algorithm: *algorithm,
+                }))
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    // Remove a key from the token, such as when a credential is deleted.
+    // key - The key to remove.
+    pub fn destroy_key(&self, key: Pkcs11Key) -> Result<(), Error> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        return check("C_DestroyObject", unsafe {
+            (self.module.list().destroy_object)(self.handle, key.handle)
+        });
+    }
+}
+
+impl Drop for Pkcs11Session<'_> {
+    fn drop(&mut self) {
+        // SAFETY: Keys borrow the session, so nothing uses the handle after it is closed.
+        unsafe {
+            (self.module.list().close_session)(self.handle);
+        }
+    }
+}
+
+impl fmt::Debug for Pkcs11Session<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("Pkcs11Session")
+            .field("handle", &self.handle)
+            .finish_non_exhaustive();
+    }
+}
+
+// An HMAC key held by a token, referred to by its handle.
+#[derive(Debug)]
+pub struct Pkcs11Key<'a> {
+    session: &'a Pkcs11Session<'a>, // The session the key was found with.
+    handle: CkObjectHandle,         // The key's object handle.
+    algorithm: Algorithm,           // The hash algorithm the key is used with.
+}
+
+impl HmacKey for Pkcs11Key<'_> {
+    fn sign(&self, message: &[u8], out: &mut [u8; MAX_TAG_BYTES]) -> Result<usize, Error> {
+        let mechanism = CkMechanism {
+            mechanism: match self.algorithm {
+                Algorithm::Sha1 => CKM_SHA_1_HMAC,
+                Algorithm::Sha256 => CKM_SHA256_HMAC,
+                Algorithm::Sha512 => CKM_SHA512_HMAC,
+            },
+            parameter: ptr::null(),
+            parameter_len: 0,
+        };
+
+        let session = self.session;
+        let list = session.module.list();
+        let _guard = session.lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        match check("C_SignInit", unsafe {
+            (list.sign_init)(session.handle, &mechanism, self.handle)
+        }) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+
+        let mut len = MAX_TAG_BYTES as CkUlong;
+        match check("C_Sign", unsafe {
+            (list.sign)(
+                session.handle,
+                message.as_ptr(),
+                message.len() as CkUlong,
+                out.as_mut_ptr(),
+                &mut len,
+            )
+        }) {
+            Ok(_) => return Ok(len as usize),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_pkcs11_load_errors() {
+        use super::*;
+        match Pkcs11Module::load(&String::from("/nonexistent/libpkcs11.so")) {
+            Ok(_) => panic!("A missing module should not load."),
+            Err(e) => assert!(matches!(e, Error::Pkcs11(_))),
+        }
+
+        // The C library is loaded in every process but doesn't export C_GetFunctionList.
+        #[cfg(all(target_os = "linux", target_env = "gnu"))]
+        match Pkcs11Module::load(&String::from("libc.so.6")) {
+            Ok(_) => panic!("The C library is not a PKCS#11 module."),
+            Err(e) => assert!(e.to_string().contains("isn't a PKCS#11 module")),
+        }
+    }
+
+    // Runs against a SoftHSMv2 token, see the PKCS#11 section of the README.
+    #[test]
+    #[ignore = "needs a SoftHSMv2 token"]
+    fn test_pkcs11_key() {
+        use super::*;
+        use crate::hotp::Digits;
+        use crate::outcome::Outcome;
+        use crate::verifier::Verifier;
+        use crate::{otp, totp};
+        let env =
+            |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("{} is not set", name));
+        let module = match Pkcs11Module::load(&env("LOTP_PKCS11_MODULE")) {
+            Ok(m) => m,
+            Err(e) => panic!("An error occurred when loading the module: {}", e),
+        };
+        let slot = match module.slot_with_token(&env("LOTP_PKCS11_TOKEN")) {
+            Ok(Some(s)) => s,
+            Ok(None) => panic!("The token could not be found."),
+            Err(e) => panic!("An error occurred when finding the token: {}", e),
+        };
+        let session = match module.open_session(&slot, &env("LOTP_PKCS11_PIN")) {
+            Ok(s) => s,
+            Err(e) => panic!("An error occurred when opening a session: {}", e),
+        };
+
+        // RFC 6238 appendix B, generated inside the token and checked against the software key.
+        let secret = String::from("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        let label = format!("lotp-test-{}", std::process::id());
+        let key = match session.import_secret(&label, &secret, &Algorithm::Sha1) {
+            Ok(k) => k,
+            Err(e) => panic!("An error occurred when importing the secret: {}", e),
+        };
+
+        match totp::generate_totp_code_with_key(&key, &59, &30, &Digits::Eight) {
+            Ok(c) => assert_eq!(c.as_str(), "94287082"),
+            Err(e) => panic!("An error occurred when generating the totp: {}", e),
+        }
+
+        let found = match session.find_key(&label, &Algorithm::Sha1) {
+            Ok(Some(k)) => k,
+            Ok(None) => panic!("The imported key could not be found."),
+            Err(e) => panic!("An error occurred when finding the key: {}", e),
+        };
+        let mut totp = otp::Totp::new(&secret);
+        totp.digits = Digits::Eight;
+        let code = match otp::OtpGenerator::generate_at(&totp, &1111111109) {
+            Ok(c) => c,
+            Err(e) => panic!("An error occurred when generating the totp: {}", e),
+        };
+
+        let mut verifier = Verifier::new();
+        for expected in [true, false] {
+            match verifier.verify_totp_with_key(
+                &found,
+                &1111111109,
+                &1,
+                &code,
+                &30,
+                &Digits::Eight,
+                None,
+            ) {
+                Ok(o) => assert_eq!(o.is_valid(), expected),
+                Err(e) => panic!("An error occurred when verifying the totp: {}", e),
+            }
+        }
+
+        // RFC 4226 appendix D uses the same secret.
+        match hotp::check_hotp_with_key(&found, &0, &0, &String::from("755224"), &Digits::Six, None)
+        {
+            Ok(o) => assert_eq!(
+                o,
+                Outcome::Valid {
+                    counter: 0,
+                    step_delta: 0,
+                }
+            ),
+            Err(e) => panic!("An error occurred when checking the hotp: {}", e),
+        }
+
+        match session.destroy_key(found) {
+            Ok(_) => (),
+            Err(e) => panic!("An error occurred when destroying the key: {}", e),
+        }
+        assert!(matches!(
+            session.find_key(&label, &Algorithm::Sha1),
+            Ok(None)
+        ));
+    }
+}
@@ -0,0 +1,262 @@
+// One-time recovery codes for users who have lost their authenticator. Only PBKDF2 hashes of the
+// codes are kept, the plain codes are returned once when they are generated so they can be shown
+// to the user. Every code in a set is hashed with the same salt, so verifying a code costs a
+// single derivation however many codes are left.
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use crate::hash;
+use crate::secret;
+use ring::constant_time;
+use ring::rand::SystemRandom;
+
+// The amount of recovery codes generated by default.
+pub const DEFAULT_RECOVERY_CODES: usize = 10;
+// The amount of PBKDF2 iterations used to hash each code by default.
+pub const DEFAULT_ITERATIONS: u32 = 100_000;
+// The characters used in codes by default, digits and lowercase letters that are easily confused
+// (0, 1, i, l and o) are left out.
+pub const DEFAULT_ALPHABET: &str = "23456789abcdefghjkmnpqrstuvwxyz";
+
+// How recovery codes are written, such as xxxxx-xxxxx.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RecoveryFormat {
+    pub alphabet: String, // The characters a code is made of, each may only appear once.
+    pub groups: usize,    // The amount of groups in a code.
+    pub group_len: usize, // The amount of characters in each group.
+    pub separator: char,  // The character written between groups, ignored when verifying.
+}
+
+impl RecoveryFormat {
+    // Creates the default format, two groups of five characters from DEFAULT_ALPHABET separated by
+    // a hyphen, about 49 bits per code.
+    pub fn new() -> RecoveryFormat {
+        return RecoveryFormat {
+            alphabet: String::from(DEFAULT_ALPHABET),
+            groups: 2,
+            group_len: 5,
+            separator: '-',
+        };
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        match secret::validate_alphabet(&self.alphabet) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+
+        if self.alphabet.contains(self.separator) || self.separator.is_whitespace() {
+            return Err(Error::InvalidFormat(String::from(
+                "The separator can't be whitespace or part of the alphabet.",
+            )));
+        } else if self.groups == 0 || self.group_len == 0 {
+            return Err(Error::InvalidFormat(String::from(
+                "Codes must have at least one group of one character.",
+            )));
+        }
+
+        return Ok(());
+    }
+
+    // Removes separators and whitespace, and lowercases the code when the alphabet has no
+    // uppercase letters, so codes can be typed loosely.
+    #[allow(clippy::ptr_arg)]
+    fn normalize(&self, code: &String) -> String {
+        let fold = !self.alphabet.chars().any(|c| c.is_ascii_uppercase());
+
+        return code
+            .chars()
+            .filter(|c| *c != self.separator && !c.is_whitespace())
+            .map(|c| if fold { c.to_ascii_lowercase() } else { c })
+            .collect();
+    }
+}
+
+impl Default for RecoveryFormat {
+    fn default() -> RecoveryFormat {
+        return RecoveryFormat::new();
+    }
+}
+
+// The hashes of a user's unused recovery codes, this is what should be persisted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RecoveryCodes {
+    format: RecoveryFormat, // The format the codes were generated in.
+    iterations: u32,        // The amount of PBKDF2 iterations used to hash each code.
+    salt: Vec<u8>,          // The salt shared by every code in the set.
+    hashes: Vec<Vec<u8>>,   // The hashes of the unused codes.
+}
+
+impl RecoveryCodes {
+    // Generate new recovery codes, returning the plain codes to show the user and the hashes to
+    // store.
+    // count - The amount of codes to generate.
+    // format - How the codes are written.
+    // iterations - The amount of PBKDF2 iterations used to hash each code, at least one.
+    #[allow(clippy::needless_late_init)]
+    pub fn generate(
+        count: &usize,
+        format: &RecoveryFormat,
+        iterations: &u32,
+    ) -> Result<(Vec<String>, RecoveryCodes), Error> {
+        match format.validate() {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+
+        let salt;
+        match hash::random_salt() {
+            Ok(s) => salt = s,
+            Err(e) => return Err(e),
+        }
+
+        let rng = SystemRandom::new();
+        let mut codes = Vec::with_capacity(*count);
+        let mut hashes = Vec::with_capacity(*count);
+
+        for _ in 0..*count {
+            let code;
+            match random_code(&rng, format) {
+                Ok(c) => code = c,
+                Err(e) => return Err(e),
+            }
+
+            match hash::derive_with_salt(format.normalize(&code).as_bytes(), &salt, iterations) {
+                Ok(h) => hashes.push(h),
+                Err(e) => return Err(e),
+            }
+
+            codes.push(code);
+        }
+
+        return Ok((
+            codes,
+            RecoveryCodes {
+                format: format.clone(),
+                iterations: *iterations,
+                salt,
+                hashes,
+            },
+        ));
+    }
+
+    // Returns the amount of codes that haven't been used.
+    pub fn remaining(&self) -> usize {
+        return self.hashes.len();
+    }
+
+    // Verify a recovery code, consuming it if it is valid so that it can't be used again. The code
+    // is hashed once and compared in constant time against every unused code, so the time taken
+    // doesn't depend on which code matched. The codes should be persisted again after a successful
+    // verification.
+    // comparison - The code entered by the user, separators, whitespace and case are ignored.
+    #[allow(clippy::needless_late_init)]
+    pub fn verify(&mut self, comparison: &String) -> bool {
+        let normalized = self.format.normalize(comparison);
+        let hash;
+        match hash::derive_with_salt(normalized.as_bytes(), &self.salt, &self.iterations) {
+            Ok(h) => hash = h,
+            Err(_) => return false,
+        }
+
+        let mut matched = None;
+        for (i, h) in self.hashes.iter().enumerate() {
+            if constant_time::verify_slices_are_equal(h, &hash).is_ok() && matched.is_none() {
+                matched = Some(i);
+            }
+        }
+
+        match matched {
+            Some(i) => {
+                self.hashes.remove(i);
+                return true;
+            }
+            None => return false,
+        }
+    }
+}
+
+// Build a code from the format's alphabet and groups.
+fn random_code(rng: &SystemRandom, format: &RecoveryFormat) -> Result<String, Error> {
+    let mut code = String::with_capacity(format.groups * (format.group_len + 1));
+
+    for group in 0..format.groups {
+        if group > 0 {
+            code.push(format.separator);
+        }
+
+        match secret::push_random_chars(rng, &format.alphabet, &format.group_len, &mut code) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    return Ok(code);
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_recovery_codes() {
+        use super::*;
+        let (codes, mut stored) = match RecoveryCodes::generate(&5, &RecoveryFormat::new(), &10) {
+            Ok(r) => r,
+            Err(e) => panic!(
+                "An error occurred when generating the recovery codes: {}",
+                e
+            ),
+        };
+
+        assert_eq!(codes.len(), 5);
+        assert_eq!(stored.remaining(), 5);
+        assert!(codes
+            .iter()
+            .all(|c| c.len() == 11 && c.as_bytes()[5] == b'-'));
+
+        assert!(!stored.verify(&String::from("aaaaa-aaaaa")));
+        assert!(stored.verify(&codes[2].to_uppercase().replace('-', " ")));
+        assert_eq!(stored.remaining(), 4);
+
+        // A code can only be used once.
+        assert!(!stored.verify(&codes[2]));
+        assert!(stored.verify(&codes[0]));
+        assert_eq!(stored.remaining(), 3);
+    }
+
+    #[test]
+    fn test_recovery_format() {
+        use super::*;
+        let format = RecoveryFormat {
+            alphabet: String::from("0123456789"),
+            groups: 3,
+            group_len: 4,
+            separator: ' ',
+        };
+        assert!(RecoveryCodes::generate(&1, &format, &10).is_err());
+
+        let format = RecoveryFormat {
+            alphabet: String::from("0123456789"),
+            groups: 3,
+            group_len: 4,
+            separator: '.',
+        };
+        match RecoveryCodes::generate(&1, &format, &10) {
+            Ok((codes, _)) => {
+                assert_eq!(codes[0].len(), 14);
+                assert!(codes[0].chars().all(|c| c == '.' || c.is_ascii_digit()));
+            }
+            Err(e) => panic!(
+                "An error occurred when generating the recovery codes: {}",
+                e
+            ),
+        }
+
+        let format = RecoveryFormat {
+            alphabet: String::from("abca"),
+            ..RecoveryFormat::new()
+        };
+        assert!(RecoveryCodes::generate(&1, &format, &10).is_err());
+        assert!(RecoveryCodes::generate(&1, &RecoveryFormat::new(), &0).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+// Rotating a credential's secret without locking out users whose authenticator still has the old
+// secret. After a rotation codes from either credential are accepted until the new credential is
+// first used or the grace period ends, then the old credential is discarded.
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use crate::otp::{Credential, OtpGenerator, OtpVerifier};
+use crate::outcome::Outcome;
+use crate::throttle::AttemptState;
+use alloc::string::String;
+
+// A credential that may be part way through a rotation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RotatingCredential {
+    current: Credential,          // The newest credential, used to generate codes.
+    previous: Option<Credential>, // The credential being replaced, if any.
+    previous_until: u64,          // The unix time the replaced credential is dropped at.
+}
+
+impl RotatingCredential {
+    // Creates a credential that isn't being rotated.
+    // credential - The credential.
+    pub fn new(credential: &Credential) -> RotatingCredential {
+        return RotatingCredential {
+            current: credential.clone(),
+            previous: None,
+            previous_until: 0,
+        };
+    }
+
+    // Replace the credential, keeping the current one until the new one is used or the grace
+    // period ends. A credential that was already being replaced is discarded.
+    // credential - The new credential.
+    // now - The current unix time in seconds.
+    // grace_secs - The amount of seconds the current credential is still accepted for.
+    pub fn rotate(&mut self, credential: &Credential, now: &u64, grace_secs: &u64) {
+        self.previous = Some(core::mem::replace(&mut self.current, credential.clone()));
+        self.previous_until = now.saturating_add(*grace_secs);
+    }
+
+    // Returns the newest credential.
+    pub fn current(&self) -> &Credential {
+        return &self.current;
+    }
+
+    // Returns the credential being replaced if it is still accepted at the time now.
+    // now - The current unix time in seconds.
+    pub fn previous(&self, now: &u64) -> Option<&Credential> {
+        if *now >= self.previous_until {
+            return None;
+        }
+
+        return self.previous.as_ref();
+    }
+}
+
+impl OtpGenerator for RotatingCredential {
+    // Generate a code from the newest credential.
+    fn generate_at(&self, now: &u64) -> Result<String, Error> {
+        return self.current.generate_at(now);
+    }
+}
+
+impl OtpVerifier for RotatingCredential {
+    // Verify a code against the newest credential, then against the credential being replaced.
+    // A code accepted by the newest credential discards the one being replaced, as does the end of
+    // the grace period. An error from the newest credential is only returned if the credential
+    // being replaced doesn't accept the code. The credential should be persisted after every
+    // verification.
+    #[allow(clippy::needless_late_init)]
+    fn verify_at(&mut self, comparison: &String, now: &u64) -> Result<Outcome, Error> {
+        if *now >= self.previous_until {
+            self.previous = None;
+        }
+
+        let current = self.current.verify_at(comparison, now);
+        if let Ok(o @ Outcome::Valid { .. }) = current {
+            self.previous = None;
+            return Ok(o);
+        }
+
+        let previous;
+        match self.previous {
+            Some(ref mut p) => previous = Some(p.verify_at(comparison, now)),
+            None => previous = None,
+        }
+
+        match (current, previous) {
+            (_, Some(Ok(o @ Outcome::Valid { .. }))) => return Ok(o),
+            (Err(e), _) => return Err(e),
+            (Ok(_), Some(Ok(Outcome::Replayed))) => return Ok(Outcome::Replayed),
+            (Ok(Outcome::Malformed), Some(Ok(Outcome::Invalid))) => return Ok(Outcome::Invalid),
+            (Ok(_), Some(Err(e))) => return Err(e),
+            (Ok(o), _) => return Ok(o),
+        }
+    }
+
+    // The attempts of the newest credential, which is the one being used.
+    fn attempts(&self) -> Option<AttemptState> {
+        return self.current.attempts();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_rotation_grace_period() {
+        use super::*;
+        use crate::otp::Totp;
+        let old = Totp::new(&String::from("abcdef234567abcdef234567"));
+        let new = Totp::new(&String::from("zyxwvu765432zyxwvu765432"));
+        let mut credential = RotatingCredential::new(&Credential::Totp(old.clone()));
+        credential.rotate(&Credential::Totp(new.clone()), &1000, &3600);
+
+        // The user's authenticator still has the old secret.
+        let old_code = old.generate_at(&1000).unwrap();
+        match credential.verify_at(&old_code, &1000) {
+            Ok(o) => assert!(o.is_valid()),
+            Err(e) => panic!("An error occurred when verifying the code: {}", e),
+        }
+        match credential.verify_at(&old_code, &1000) {
+            Ok(o) => assert_eq!(o, Outcome::Replayed),
+            Err(e) => panic!("An error occurred when verifying the code: {}", e),
+        }
+        assert!(credential.previous(&1000).is_some());
+
+        // Once the new secret is used the old one is discarded.
+        let new_code = new.generate_at(&1060).unwrap();
+        match credential.verify_at(&new_code, &1060) {
+            Ok(o) => assert!(o.is_valid()),
+            Err(e) => panic!("An error occurred when verifying the code: {}", e),
+        }
+        assert!(credential.previous(&1060).is_none());
+
+        let old_code = old.generate_at(&1090).unwrap();
+        match credential.verify_at(&old_code, &1090) {
+            Ok(o) => assert!(!o.is_valid()),
+            Err(e) => panic!("An error occurred when verifying the code: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_rotation_current_error() {
+        use super::*;
+        use crate::otp::Totp;
+        let old = Totp::new(&String::from("abcdef234567abcdef234567"));
+        let mut credential = RotatingCredential::new(&Credential::Totp(old.clone()));
+
+        // A new credential that can't be checked, such as one restored without its secret, still
+        // lets the old credential accept codes.
+        credential.rotate(&Credential::Totp(Totp::new(&String::new())), &1000, &3600);
+        let old_code = old.generate_at(&1000).unwrap();
+        match credential.verify_at(&old_code, &1000) {
+            Ok(o) => assert!(o.is_valid()),
+            Err(e) => panic!("An error occurred when verifying the code: {}", e),
+        }
+
+        let wrong = if old_code == "000000" {
+            "111111"
+        } else {
+            "000000"
+        };
+        assert_eq!(
+            credential.verify_at(&String::from(wrong), &1000),
+            Err(Error::MissingSecret)
+        );
+    }
+
+    #[test]
+    fn test_rotation_deadline() {
+        use super::*;
+        use crate::otp::Hotp;
+        let old = Hotp::new(&String::from("abcdef234567abcdef234567"), &0);
+        let new = Hotp::new(&String::from("zyxwvu765432zyxwvu765432"), &0);
+        let mut credential = RotatingCredential::new(&Credential::Hotp(old.clone()));
+        credential.rotate(&Credential::Hotp(new.clone()), &1000, &60);
+
+        let old_code = old.generate_at(&0).unwrap();
+        match credential.verify_at(&old_code, &1060) {
+            Ok(o) => assert!(!o.is_valid()),
+            Err(e) => panic!("An error occurred when verifying the code: {}", e),
+        }
+        assert!(credential.previous(&0).is_none());
+        assert_eq!(credential.generate_at(&0), new.generate_at(&0));
+    }
+}
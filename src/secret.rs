@@ -0,0 +1,102 @@
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use ring::rand::{SecureRandom, SystemRandom};
+
+// The amount of random bytes in a generated secret, 160 bits as recommended by RFC 4226.
+pub const DEFAULT_SECRET_BYTES: usize = 20;
+// The least amount of random bytes in a generated secret, 128 bits as required by RFC 4226.
+pub const MIN_SECRET_BYTES: usize = 16;
+
+// Generate a random secret, encoded as base-32 without padding.
+// bytes - The amount of random bytes in the secret, at least MIN_SECRET_BYTES.
+pub fn generate_secret(bytes: &usize) -> Result<String, Error> {
+    if *bytes < MIN_SECRET_BYTES {
+        return Err(Error::SecretTooShort(*bytes));
+    }
+
+    let mut buffer = vec![0u8; *bytes];
+
+    match SystemRandom::new().fill(&mut buffer) {
+        Ok(_) => {
+            return Ok(base32::encode(
+                base32::Alphabet::RFC4648 { padding: false },
+                &buffer,
+            ))
+        }
+        Err(_) => return Err(Error::RandomFailure),
+    }
+}
+
+// Check that an alphabet can be used for random codes.
+// alphabet - Between 2 and 256 distinct ASCII characters.
+pub(crate) fn validate_alphabet(alphabet: &str) -> Result<(), Error> {
+    let chars = alphabet.as_bytes();
+
+    if !alphabet.is_ascii() {
+        return Err(Error::InvalidFormat(String::from(
+            "The alphabet may only contain ASCII characters.",
+        )));
+    } else if chars.len() < 2 || chars.len() > 256 {
+        return Err(Error::InvalidFormat(String::from(
+            "The alphabet must have between 2 and 256 characters.",
+        )));
+    } else if (1..chars.len()).any(|i| chars[..i].contains(&chars[i])) {
+        return Err(Error::InvalidFormat(String::from(
+            "The alphabet contains a character more than once.",
+        )));
+    }
+
+    return Ok(());
+}
+
+// Append random characters from a validated alphabet, discarding random bytes that would bias the
+// choice of character.
+// rng - The random number generator.
+// alphabet - The characters to choose from.
+// len - The amount of characters to append.
+// out - The string to append to.
+pub(crate) fn push_random_chars(
+    rng: &SystemRandom,
+    alphabet: &str,
+    len: &usize,
+    out: &mut String,
+) -> Result<(), Error> {
+    let alphabet = alphabet.as_bytes();
+    let limit = 256 - (256 % alphabet.len());
+    let mut buffer = [0u8; 32];
+    let mut written = 0;
+
+    while written < *len {
+        if rng.fill(&mut buffer).is_err() {
+            return Err(Error::RandomFailure);
+        }
+
+        for b in buffer.iter().filter(|b| (**b as usize) < limit) {
+            if written == *len {
+                break;
+            }
+
+            out.push(alphabet[*b as usize % alphabet.len()] as char);
+            written += 1;
+        }
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_generate_secret() {
+        use super::*;
+        match generate_secret(&DEFAULT_SECRET_BYTES) {
+            Ok(s) => {
+                assert_eq!(s.len(), 32);
+                assert_ne!(generate_secret(&DEFAULT_SECRET_BYTES).unwrap(), s);
+            }
+            Err(e) => panic!("An error occurred when generating the secret: {}", e),
+        }
+
+        assert_eq!(generate_secret(&10), Err(Error::SecretTooShort(10)));
+    }
+}
@@ -0,0 +1,484 @@
+// A TOTP and HOTP verifier that can be shared between threads, such as by an authentication
+// gateway. The replay and attempt state of every credential is kept in memory, split over shards
+// that are each behind their own lock, and codes are computed before any lock is taken. Entries are
+// dropped once their codes can no longer be replayed and they aren't locked out, so memory doesn't
+// grow with the amount of credentials ever seen.
+//
+// A HOTP counter can't be recovered from the time, so for HOTP the in-memory state only stops
+// concurrent requests accepting the same code. The caller must still store the matched counter
+// plus one as the credential's counter after every accepted code.
+#![allow(clippy::needless_return)]
+use crate::audit::{self, AuditObserver, Auditor};
+use crate::backend;
+use crate::error::Error;
+use crate::hotp::{self, Algorithm, Digits};
+use crate::otp::{Hotp, Totp};
+use crate::outcome::{self, Outcome};
+use crate::throttle::{AttemptState, ThrottlePolicy};
+use crate::totp;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// The amount of seconds an idle entry's attempts are kept for by default.
+pub const DEFAULT_RETENTION_SECS: u64 = 900;
+
+// The replay and attempt state of a single credential.
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    last_counter: Option<u64>, // The last accepted time step or HOTP counter.
+    attempts: AttemptState,    // The failed attempts.
+    expires_at: u64,           // The unix time after which the entry can be dropped.
+}
+
+#[derive(Debug, Default)]
+struct Shard {
+    entries: HashMap<String, Entry>,
+    next_sweep: u64, // The unix time at which expired entries are next dropped.
+}
+
+impl Shard {
+    fn sweep(&mut self, now: &u64, retention_secs: &u64) {
+        if *now < self.next_sweep {
+            return;
+        }
+
+        self.entries
+            .retain(|_, e| e.expires_at > *now || e.attempts.locked_until(now).is_some());
+        self.next_sweep = now.saturating_add(*retention_secs);
+    }
+}
+
+// A Send + Sync TOTP and HOTP verifier with sharded, expiring replay and attempt state.
+pub struct SharedVerifier<P: ThrottlePolicy + Send + Sync> {
+    shards: Vec<Mutex<Shard>>,
+    hasher: RandomState,
+    policy: P,
+    retention_secs: u64,
+    observer: Option<Arc<dyn AuditObserver + Send + Sync>>, // Receives an event for every attempt.
+}
+
+impl<P: ThrottlePolicy + Send + Sync> SharedVerifier<P> {
+    // Creates a verifier with four shards per available thread and the default retention.
+    // policy - The policy that decides when a credential is locked out.
+    pub fn new(policy: P) -> SharedVerifier<P> {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        return SharedVerifier::with_shards(policy, &(threads * 4), &DEFAULT_RETENTION_SECS);
+    }
+
+    // Creates a verifier.
+    // policy - The policy that decides when a credential is locked out.
+    // shards - The amount of independently locked shards, at least one.
+    // retention_secs - The amount of seconds the attempts of an idle credential are kept for.
+    pub fn with_shards(policy: P, shards: &usize, retention_secs: &u64) -> SharedVerifier<P> {
+        return SharedVerifier {
+            shards: (0..(*shards).max(1))
+                .map(|_| Mutex::new(Shard::default()))
+                .collect(),
+            hasher: RandomState::new(),
+            policy,
+            retention_secs: *retention_secs,
+            observer: None,
+        };
+    }
+
+    // Reports every attempt to an observer, with the credential's lockout state after it.
+    // observer - The observer that receives the events.
+    pub fn with_observer(
+        mut self,
+        observer: Arc<dyn AuditObserver + Send + Sync>,
+    ) -> SharedVerifier<P> {
+        self.observer = Some(observer);
+        return self;
+    }
+
+    // Returns the amount of credentials that state is kept for.
+    pub fn len(&self) -> usize {
+        return self
+            .shards
+            .iter()
+            .map(|s| s.lock().unwrap_or_else(|e| e.into_inner()).entries.len())
+            .sum();
+    }
+
+    // Returns true if no state is kept.
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    // Check a TOTP code at the time now, refusing to check it while the credential is locked out.
+    // The credential's secret and configuration are used, its own verifier state is not.
+    // credential_id - The identifier the state is kept under.
+    // credential - The credential the code is checked against.
+    // comparison - The code that is is to be checked if valid.
+    // now - The current unix time in seconds.
+    pub fn verify_totp_at(
+        &self,
+        credential_id: &String,
+        credential: &Totp,
+        comparison: &String,
+        now: &u64,
+    ) -> Result<Outcome, Error> {
+        let result = self.check_totp(credential_id, credential, comparison, now);
+        return self.report(credential_id, now, result);
+    }
+
+    #[allow(clippy::needless_late_init)]
+    fn check_totp(
+        &self,
+        credential_id: &String,
+        credential: &Totp,
+        comparison: &String,
+        now: &u64,
+    ) -> Result<Outcome, Error> {
+        let shard = self.shard(credential_id);
+        if let Some(until) = locked_until(shard, credential_id, now) {
+            return Ok(Outcome::LockedOut { until });
+        }
+
+        let counter;
+        match totp::counter_at(now, &credential.period) {
+            Ok(c) => counter = c,
+            Err(e) => return Err(e),
+        }
+
+        let skew = credential.skew as u64;
+        let matches;
+        match find_matches(
+            &credential.secret,
+            &credential.algorithm,
+            &credential.digits,
+            &counter.saturating_sub(skew),
+            &counter.saturating_add(skew),
+            comparison,
+        ) {
+            Ok(Some(m)) => matches = m,
+            Ok(None) => return Ok(Outcome::Malformed),
+            Err(e) => return Err(e),
+        }
+
+        // Replays are possible until the last step in the window has passed.
+        let window_end = counter
+            .saturating_add(2 * skew + 1)
+            .saturating_mul(credential.period);
+
+        return Ok(self.record(shard, credential_id, &matches, &counter, now, &window_end));
+    }
+
+    // Check a HOTP code against the credential's counter and the look-ahead window after it,
+    // refusing to check it while the credential is locked out. After a valid outcome the caller
+    // must store the matched counter plus one as the credential's counter.
+    // credential_id - The identifier the state is kept under.
+    // credential - The credential the code is checked against, with its stored counter.
+    // comparison - The code that is is to be checked if valid.
+    // now - The current unix time in seconds, used for lockouts and to expire the state.
+    pub fn verify_hotp_at(
+        &self,
+        credential_id: &String,
+        credential: &Hotp,
+        comparison: &String,
+        now: &u64,
+    ) -> Result<Outcome, Error> {
+        let result = self.check_hotp(credential_id, credential, comparison, now);
+        return self.report(credential_id, now, result);
+    }
+
+    #[allow(clippy::needless_late_init)]
+    fn check_hotp(
+        &self,
+        credential_id: &String,
+        credential: &Hotp,
+        comparison: &String,
+        now: &u64,
+    ) -> Result<Outcome, Error> {
+        if credential.exhausted {
+            return Err(Error::CounterExhausted);
+        }
+
+        let shard = self.shard(credential_id);
+        if let Some(until) = locked_until(shard, credential_id, now) {
+            return Ok(Outcome::LockedOut { until });
+        }
+
+        let matches;
+        match find_matches(
+            &credential.secret,
+            &credential.algorithm,
+            &credential.digits,
+            &credential.counter,
+            &credential.counter.saturating_add(credential.look_ahead),
+            comparison,
+        ) {
+            Ok(Some(m)) => matches = m,
+            Ok(None) => return Ok(Outcome::Malformed),
+            Err(e) => return Err(e),
+        }
+
+        return Ok(self.record(shard, credential_id, &matches, &credential.counter, now, &0));
+    }
+
+    // Reports an attempt to the observer, if there is one, with the credential's attempts after it.
+    fn report(
+        &self,
+        credential_id: &String,
+        now: &u64,
+        result: Result<Outcome, Error>,
+    ) -> Result<Outcome, Error> {
+        match self.observer {
+            Some(ref observer) => {
+                let guard = self
+                    .shard(credential_id)
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner());
+                let attempts = guard
+                    .entries
+                    .get(credential_id)
+                    .map_or(AttemptState::new(), |e| e.attempts);
+                drop(guard);
+
+                let auditor = Auditor::new(credential_id, observer.as_ref());
+                return audit::report(Some(&auditor), Some(*now), result, Some(attempts));
+            }
+            None => return result,
+        }
+    }
+
+    // Returns the shard that a credential's state is kept in.
+    fn shard(&self, credential_id: &String) -> &Mutex<Shard> {
+        return &self.shards[self.hasher.hash_one(credential_id) as usize % self.shards.len()];
+    }
+
+    // Records the result of a check, accepting the first match after the last accepted counter.
+    // matches - The counters in the window whose code matched, in ascending order.
+    // expected - The current time step or the credential's counter.
+    // window_end - The unix time until which the matches could be replayed.
+    #[allow(clippy::ptr_arg)]
+    fn record(
+        &self,
+        shard: &Mutex<Shard>,
+        credential_id: &String,
+        matches: &[u64],
+        expected: &u64,
+        now: &u64,
+        window_end: &u64,
+    ) -> Outcome {
+        let mut guard = shard.lock().unwrap_or_else(|e| e.into_inner());
+        guard.sweep(now, &self.retention_secs);
+
+        let entry = guard.entries.entry(credential_id.clone()).or_insert(Entry {
+            last_counter: None,
+            attempts: AttemptState::new(),
+            expires_at: 0,
+        });
+
+        // The state may have changed while the codes were computed.
+        if let Some(until) = entry.attempts.locked_until(now) {
+            return Outcome::LockedOut { until };
+        }
+
+        entry.expires_at = entry
+            .expires_at
+            .max(*window_end)
+            .max(now.saturating_add(self.retention_secs));
+
+        let fresh = matches
+            .iter()
+            .find(|i| entry.last_counter.is_none_or(|last| **i > last));
+
+        match fresh {
+            Some(counter) => {
+                entry.last_counter = Some(*counter);
+                entry.attempts.record_success();
+                return Outcome::Valid {
+                    counter: *counter,
+                    step_delta: outcome::step_delta(counter, expected),
+                };
+            }
+            None => {
+                entry.attempts.record_failure(&self.policy, now);
+                if matches.is_empty() {
+                    return Outcome::Invalid;
+                }
+
+                return Outcome::Replayed;
+            }
+        }
+    }
+}
+
+// Returns the time the credential is locked out until, if it is.
+fn locked_until(shard: &Mutex<Shard>, credential_id: &String, now: &u64) -> Option<u64> {
+    let guard = shard.lock().unwrap_or_else(|e| e.into_inner());
+    match guard.entries.get(credential_id) {
+        Some(e) => return e.attempts.locked_until(now),
+        None => return None,
+    }
+}
+
+// Compute every counter from min to max without holding a lock, returning those whose code matches
+// or None if the code is malformed.
+#[allow(clippy::needless_late_init)]
+fn find_matches(
+    secret: &String,
+    algorithm: &Algorithm,
+    digits: &Digits,
+    min: &u64,
+    max: &u64,
+    comparison: &String,
+) -> Result<Option<Vec<u64>>, Error> {
+    let key;
+    match hotp::decode_credential_key(secret, algorithm) {
+        Ok(k) => key = k,
+        Err(e) => return Err(e),
+    }
+
+    let code;
+    match outcome::parse_code(comparison, digits) {
+        Some(c) => code = c,
+        None => return Ok(None),
+    }
+
+    let mut matches = Vec::new();
+    for i in *min..=*max {
+        match hotp::truncated_hotp(&key, &i) {
+            Ok(value) if backend::codes_equal(&(value % digits.modulus()), &code) => {
+                matches.push(i)
+            }
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    return Ok(Some(matches));
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_shared_verifier() {
+        use super::*;
+        use crate::otp::OtpGenerator;
+        use crate::throttle::FixedLockout;
+        let policy = FixedLockout {
+            max_failures: 2,
+            lockout_secs: 60,
+        };
+        let verifier = SharedVerifier::with_shards(policy, &1, &120);
+        let id = String::from("user-1");
+        let totp = Totp::new(&String::from("abcdef234567abcdef234567"));
+        let code = totp.generate_at(&1000).unwrap();
+
+        match verifier.verify_totp_at(&id, &totp, &code, &1000) {
+            Ok(o) => assert!(o.is_valid()),
+            Err(e) => panic!("An error occurred when verifying the totp: {}", e),
+        }
+
+        for expected in [Outcome::Replayed, Outcome::Replayed] {
+            match verifier.verify_totp_at(&id, &totp, &code, &1010) {
+                Ok(o) => assert_eq!(o, expected),
+                Err(e) => panic!("An error occurred when verifying the totp: {}", e),
+            }
+        }
+
+        match verifier.verify_totp_at(&id, &totp, &code, &1020) {
+            Ok(o) => assert_eq!(o, Outcome::LockedOut { until: 1070 }),
+            Err(e) => panic!("An error occurred when verifying the totp: {}", e),
+        }
+
+        // Another credential is unaffected.
+        match verifier.verify_totp_at(&String::from("user-2"), &totp, &code, &1020) {
+            Ok(o) => assert!(o.is_valid()),
+            Err(e) => panic!("An error occurred when verifying the totp: {}", e),
+        }
+        assert_eq!(verifier.len(), 2);
+
+        // Idle entries are dropped once they can't be replayed and aren't locked out.
+        let later = totp.generate_at(&5000).unwrap();
+        match verifier.verify_totp_at(&String::from("user-3"), &totp, &later, &5000) {
+            Ok(o) => assert!(o.is_valid()),
+            Err(e) => panic!("An error occurred when verifying the totp: {}", e),
+        }
+        assert_eq!(verifier.len(), 1);
+    }
+
+    #[test]
+    fn test_shared_verifier_hotp() {
+        use super::*;
+        use crate::throttle::FixedLockout;
+        let verifier = SharedVerifier::with_shards(
+            FixedLockout {
+                max_failures: 0,
+                lockout_secs: 0,
+            },
+            &1,
+            &120,
+        );
+        let id = String::from("user-1");
+        let secret = String::from("abcdef234567abcdef234567");
+        let mut token = Hotp::new(&secret, &20);
+        let code = |counter: u64| hotp::generate_hotp_string(&counter, &secret, &Digits::Six);
+
+        match verifier.verify_hotp_at(&id, &token, &code(22).unwrap(), &1000) {
+            Ok(o) => assert_eq!(
+                o,
+                Outcome::Valid {
+                    counter: 22,
+                    step_delta: 2,
+                }
+            ),
+            Err(e) => panic!("An error occurred when verifying the hotp: {}", e),
+        }
+
+        // Before the new counter is stored the same code, or an earlier one, is a replay.
+        for counter in [22, 21] {
+            match verifier.verify_hotp_at(&id, &token, &code(counter).unwrap(), &1000) {
+                Ok(o) => assert_eq!(o, Outcome::Replayed),
+                Err(e) => panic!("An error occurred when verifying the hotp: {}", e),
+            }
+        }
+
+        token.counter = 23;
+        match verifier.verify_hotp_at(&id, &token, &code(23).unwrap(), &1000) {
+            Ok(o) => assert!(o.is_valid()),
+            Err(e) => panic!("An error occurred when verifying the hotp: {}", e),
+        }
+
+        token.exhausted = true;
+        assert_eq!(
+            verifier.verify_hotp_at(&id, &token, &code(24).unwrap(), &1000),
+            Err(Error::CounterExhausted)
+        );
+    }
+
+    #[test]
+    fn test_shared_verifier_threads() {
+        use super::*;
+        use crate::otp::OtpGenerator;
+        use crate::throttle::FixedLockout;
+        let verifier = SharedVerifier::new(FixedLockout {
+            max_failures: 5,
+            lockout_secs: 60,
+        });
+        let totp = Totp::new(&String::from("abcdef234567abcdef234567"));
+        let code = totp.generate_at(&1000).unwrap();
+
+        // The same code submitted for one credential from many threads is accepted only once.
+        let accepted: usize = thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    s.spawn(|| {
+                        verifier
+                            .verify_totp_at(&String::from("user-1"), &totp, &code, &1000)
+                            .unwrap()
+                            .is_valid() as usize
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+
+        assert_eq!(accepted, 1);
+    }
+}
@@ -0,0 +1,205 @@
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use crate::otp::Credential;
+use crate::storage::{CredentialStore, Record};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// A store that keeps every credential in a single JSON file. Every update writes the whole file to
+// a temporary file next to it and renames it over the original, so the file is never left half
+// written. Updates are serialized within the process, the file must not be shared between
+// processes.
+#[derive(Debug)]
+pub struct JsonFileStore {
+    path: PathBuf,   // The path of the JSON file.
+    lock: Mutex<()>, // Held while reading and writing the file.
+}
+
+impl JsonFileStore {
+    // Creates a store backed by the file at path, the file is created on the first insert.
+    // path - The path of the JSON file.
+    pub fn new<P: AsRef<Path>>(path: P) -> JsonFileStore {
+        return JsonFileStore {
+            path: path.as_ref().to_path_buf(),
+            lock: Mutex::new(()),
+        };
+    }
+
+    #[allow(clippy::needless_late_init)]
+    fn read(&self) -> Result<BTreeMap<String, Record>, Error> {
+        let contents;
+        match fs::read_to_string(&self.path) {
+            Ok(c) => contents = c,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => return Err(Error::Storage(e.to_string())),
+        }
+
+        match serde_json::from_str(&contents) {
+            Ok(records) => return Ok(records),
+            Err(e) => return Err(Error::Storage(e.to_string())),
+        }
+    }
+
+    #[allow(clippy::needless_late_init)]
+    fn write(&self, records: &BTreeMap<String, Record>) -> Result<(), Error> {
+        let contents;
+        match serde_json::to_vec_pretty(records) {
+            Ok(c) => contents = c,
+            Err(e) => return Err(Error::Storage(e.to_string())),
+        }
+
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".tmp");
+
+        let result = File::create(&temp_path)
+            .and_then(|mut file| {
+                file.write_all(&contents)?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&temp_path, &self.path));
+
+        match result {
+            Ok(_) => (),
+            Err(e) => {
+                let _ = fs::remove_file(&temp_path);
+                return Err(Error::Storage(e.to_string()));
+            }
+        }
+
+        match self.sync_directory() {
+            Ok(_) => return Ok(()),
+            Err(e) => return Err(Error::Storage(e.to_string())),
+        }
+    }
+
+    // Flushes the directory holding the file, so that the rename survives a crash.
+    #[cfg(unix)]
+    fn sync_directory(&self) -> std::io::Result<()> {
+        let directory = match self.path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+
+        return File::open(directory).and_then(|d| d.sync_all());
+    }
+
+    // Directories can't be opened as files on other platforms, where the rename is relied on.
+    #[cfg(not(unix))]
+    fn sync_directory(&self) -> std::io::Result<()> {
+        return Ok(());
+    }
+
+    // Reads the records, applies the update and writes the records back if the update returns
+    // true.
+    fn update<F>(&self, update: F) -> Result<bool, Error>
+    where
+        F: FnOnce(&mut BTreeMap<String, Record>) -> Result<bool, Error>,
+    {
+        let _guard;
+        match self.lock.lock() {
+            Ok(g) => _guard = g,
+            Err(_) => {
+                return Err(Error::Storage(String::from(
+                    "The store's lock is poisoned.",
+                )))
+            }
+        }
+
+        let mut records;
+        match self.read() {
+            Ok(r) => records = r,
+            Err(e) => return Err(e),
+        }
+
+        match update(&mut records) {
+            Ok(true) => (),
+            Ok(false) => return Ok(false),
+            Err(e) => return Err(e),
+        }
+
+        match self.write(&records) {
+            Ok(_) => return Ok(true),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl CredentialStore for JsonFileStore {
+    fn load(&self, id: &String) -> Result<Option<Record>, Error> {
+        let _guard;
+        match self.lock.lock() {
+            Ok(g) => _guard = g,
+            Err(_) => {
+                return Err(Error::Storage(String::from(
+                    "The store's lock is poisoned.",
+                )))
+            }
+        }
+
+        match self.read() {
+            Ok(mut records) => return Ok(records.remove(id)),
+            Err(e) => return Err(e),
+        }
+    }
+
+    fn insert(&self, id: &String, credential: &Credential) -> Result<bool, Error> {
+        return self.update(|records| {
+            if records.contains_key(id) {
+                return Ok(false);
+            }
+
+            records.insert(
+                id.clone(),
+                Record {
+                    version: 0,
+                    credential: credential.clone(),
+                },
+            );
+
+            return Ok(true);
+        });
+    }
+
+    fn compare_and_swap(
+        &self,
+        id: &String,
+        version: &u64,
+        credential: &Credential,
+    ) -> Result<bool, Error> {
+        return self.update(|records| match records.get_mut(id) {
+            Some(record) if record.version == *version => match record.version.checked_add(1) {
+                Some(next) => {
+                    record.version = next;
+                    record.credential = credential.clone();
+                    return Ok(true);
+                }
+                None => return Err(super::version_exhausted()),
+            },
+            _ => return Ok(false),
+        });
+    }
+
+    fn remove(&self, id: &String) -> Result<bool, Error> {
+        return self.update(|records| Ok(records.remove(id).is_some()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_json_file_store() {
+        use super::*;
+        use std::env;
+        use std::process;
+
+        let path = env::temp_dir().join(format!("lotp-store-{}.json", process::id()));
+        let _ = fs::remove_file(&path);
+
+        crate::storage::check_store(&JsonFileStore::new(&path));
+
+        let _ = fs::remove_file(&path);
+    }
+}
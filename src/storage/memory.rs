@@ -0,0 +1,129 @@
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use crate::otp::Credential;
+use crate::storage::{CredentialStore, Record};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// A store that keeps credentials in memory, for tests and single process deployments that
+// persist credentials some other way.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    records: Mutex<HashMap<String, Record>>, // The stored credentials by ID.
+}
+
+impl MemoryStore {
+    // Creates an empty store.
+    pub fn new() -> MemoryStore {
+        return MemoryStore {
+            records: Mutex::new(HashMap::new()),
+        };
+    }
+}
+
+impl CredentialStore for MemoryStore {
+    fn load(&self, id: &String) -> Result<Option<Record>, Error> {
+        match self.records.lock() {
+            Ok(records) => return Ok(records.get(id).cloned()),
+            Err(_) => {
+                return Err(Error::Storage(String::from(
+                    "The store's lock is poisoned.",
+                )))
+            }
+        }
+    }
+
+    fn insert(&self, id: &String, credential: &Credential) -> Result<bool, Error> {
+        let mut records;
+        match self.records.lock() {
+            Ok(r) => records = r,
+            Err(_) => {
+                return Err(Error::Storage(String::from(
+                    "The store's lock is poisoned.",
+                )))
+            }
+        }
+
+        if records.contains_key(id) {
+            return Ok(false);
+        }
+
+        records.insert(
+            id.clone(),
+            Record {
+                version: 0,
+                credential: credential.clone(),
+            },
+        );
+
+        return Ok(true);
+    }
+
+    fn compare_and_swap(
+        &self,
+        id: &String,
+        version: &u64,
+        credential: &Credential,
+    ) -> Result<bool, Error> {
+        let mut records;
+        match self.records.lock() {
+            Ok(r) => records = r,
+            Err(_) => {
+                return Err(Error::Storage(String::from(
+                    "The store's lock is poisoned.",
+                )))
+            }
+        }
+
+        match records.get_mut(id) {
+            Some(record) if record.version == *version => match record.version.checked_add(1) {
+                Some(next) => {
+                    record.version = next;
+                    record.credential = credential.clone();
+                    return Ok(true);
+                }
+                None => return Err(super::version_exhausted()),
+            },
+            _ => return Ok(false),
+        }
+    }
+
+    fn remove(&self, id: &String) -> Result<bool, Error> {
+        match self.records.lock() {
+            Ok(mut records) => return Ok(records.remove(id).is_some()),
+            Err(_) => {
+                return Err(Error::Storage(String::from(
+                    "The store's lock is poisoned.",
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_memory_store() {
+        use super::*;
+        crate::storage::check_store(&MemoryStore::new());
+    }
+
+    #[test]
+    fn test_memory_store_version_exhausted() {
+        use super::*;
+        use crate::otp::Totp;
+        let store = MemoryStore::new();
+        let id = String::from("user@example.com");
+        let credential = Credential::Totp(Totp::new(&String::from("abcdef234567abcdef234567")));
+        assert!(store.insert(&id, &credential).unwrap());
+
+        if let Some(record) = store.records.lock().unwrap().get_mut(&id) {
+            record.version = u64::MAX;
+        }
+
+        assert_eq!(
+            store.compare_and_swap(&id, &u64::MAX, &credential),
+            Err(crate::storage::version_exhausted())
+        );
+    }
+}
@@ -0,0 +1,131 @@
+// Storage for credentials and their state. Verification changes a credential's state (HOTP
+// counters, replay, drift and lockout state), so stores update credentials with compare-and-swap
+// on a version number: a credential is loaded, verified and only written back if no other
+// verification has updated it in the meantime.
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use crate::otp::Credential;
+
+#[cfg(feature = "json-file")]
+mod json_file;
+mod memory;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(feature = "json-file")]
+pub use json_file::JsonFileStore;
+pub use memory::MemoryStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+// A stored credential and its version, the version increases every time the credential is updated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Record {
+    pub version: u64,           // The version of the credential.
+    pub credential: Credential, // The stored credential.
+}
+
+// The error returned when a credential's version can't be increased without overflowing.
+pub(crate) fn version_exhausted() -> Error {
+    return Error::Storage(String::from(
+        "The credential's version can't be increased any further.",
+    ));
+}
+
+// A store of credentials identified by a string ID.
+pub trait CredentialStore {
+    // Load a credential, returning None if there is no credential with the ID.
+    // id - The ID of the credential.
+    #[allow(clippy::ptr_arg)]
+    fn load(&self, id: &String) -> Result<Option<Record>, Error>;
+
+    // Store a new credential with version 0, returning false if the ID is already used.
+    // id - The ID of the credential.
+    // credential - The credential to store.
+    #[allow(clippy::ptr_arg)]
+    fn insert(&self, id: &String, credential: &Credential) -> Result<bool, Error>;
+
+    // Replace a credential only if it is still at the expected version, returning true if it was
+    // replaced. The version of a replaced credential is increased by one.
+    // id - The ID of the credential.
+    // version - The version of the credential when it was loaded.
+    // credential - The updated credential.
+    #[allow(clippy::ptr_arg)]
+    fn compare_and_swap(
+        &self,
+        id: &String,
+        version: &u64,
+        credential: &Credential,
+    ) -> Result<bool, Error>;
+
+    // Remove a credential, returning false if there was no credential with the ID.
+    // id - The ID of the credential.
+    #[allow(clippy::ptr_arg)]
+    fn remove(&self, id: &String) -> Result<bool, Error>;
+}
+
+// Checks that a store behaves as described by CredentialStore.
+#[cfg(test)]
+pub(crate) fn check_store(store: &dyn CredentialStore) {
+    use crate::otp::{Hotp, OtpGenerator, OtpVerifier, Totp};
+    use crate::outcome::Outcome;
+    use crate::throttle::FixedLockout;
+
+    let id = String::from("user@example.com");
+    let secret = String::from("abcdef234567abcdef234567");
+    let credential = Credential::Totp(Totp::new(&secret));
+
+    assert_eq!(store.load(&id).unwrap(), None);
+    assert!(store.insert(&id, &credential).unwrap());
+    assert!(!store.insert(&id, &credential).unwrap());
+
+    let mut record = store.load(&id).unwrap().unwrap();
+    assert_eq!(record.version, 0);
+
+    assert_eq!(record.credential, credential);
+
+    let code = record.credential.generate_at(&1000).unwrap();
+    assert!(record
+        .credential
+        .verify_at(&code, &1000)
+        .unwrap()
+        .is_valid());
+    assert!(store
+        .compare_and_swap(&id, &record.version, &record.credential)
+        .unwrap());
+
+    // A second update from the same version is refused.
+    assert!(!store
+        .compare_and_swap(&id, &record.version, &credential)
+        .unwrap());
+
+    let updated = store.load(&id).unwrap().unwrap();
+    assert_eq!(updated.version, 1);
+    assert_eq!(updated.credential, record.credential);
+
+    assert!(store.remove(&id).unwrap());
+    assert!(!store.remove(&id).unwrap());
+    assert!(!store.compare_and_swap(&id, &1, &credential).unwrap());
+
+    // The failed attempts of a HOTP credential are stored with it.
+    let policy = FixedLockout {
+        max_failures: 1,
+        lockout_secs: 60,
+    };
+    let mut hotp = Credential::Hotp(Hotp::new(&secret, &5));
+    assert!(store.insert(&id, &hotp).unwrap());
+    assert_eq!(
+        hotp.verify_throttled_at(&policy, &String::from("000000"), &1000, None)
+            .unwrap(),
+        Outcome::Invalid
+    );
+    assert!(store.compare_and_swap(&id, &0, &hotp).unwrap());
+
+    let updated = store.load(&id).unwrap().unwrap();
+    assert_eq!(
+        updated.credential.attempts().unwrap().locked_until(&1000),
+        Some(1060)
+    );
+    assert!(store.remove(&id).unwrap());
+}
@@ -0,0 +1,168 @@
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use crate::otp::Credential;
+use crate::storage::{CredentialStore, Record};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::Mutex;
+
+// A store that keeps credentials in a SQLite table, with each credential stored as JSON.
+// Compare-and-swap is a single conditional UPDATE, so the database can be shared between
+// processes.
+#[derive(Debug)]
+pub struct SqliteStore {
+    connection: Mutex<Connection>, // The connection to the database.
+}
+
+impl SqliteStore {
+    // Opens the database at path, creating the credentials table if it doesn't exist.
+    // path - The path of the SQLite database.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteStore, Error> {
+        match Connection::open(path) {
+            Ok(c) => return SqliteStore::from_connection(c),
+            Err(e) => return Err(Error::Storage(e.to_string())),
+        }
+    }
+
+    // Uses an existing connection, creating the credentials table if it doesn't exist.
+    // connection - The connection to the database.
+    pub fn from_connection(connection: Connection) -> Result<SqliteStore, Error> {
+        let result = connection.execute(
+            "CREATE TABLE IF NOT EXISTS lotp_credentials (
+                id TEXT PRIMARY KEY NOT NULL,
+                version INTEGER NOT NULL,
+                credential TEXT NOT NULL
+            )",
+            [],
+        );
+
+        match result {
+            Ok(_) => {
+                return Ok(SqliteStore {
+                    connection: Mutex::new(connection),
+                })
+            }
+            Err(e) => return Err(Error::Storage(e.to_string())),
+        }
+    }
+
+    fn with_connection<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Connection) -> rusqlite::Result<T>,
+    {
+        match self.connection.lock() {
+            Ok(connection) => return f(&connection).map_err(|e| Error::Storage(e.to_string())),
+            Err(_) => {
+                return Err(Error::Storage(String::from(
+                    "The store's lock is poisoned.",
+                )))
+            }
+        }
+    }
+}
+
+fn to_json(credential: &Credential) -> Result<String, Error> {
+    return serde_json::to_string(credential).map_err(|e| Error::Storage(e.to_string()));
+}
+
+impl CredentialStore for SqliteStore {
+    #[allow(clippy::needless_late_init)]
+    fn load(&self, id: &String) -> Result<Option<Record>, Error> {
+        let row;
+        match self.with_connection(|c| {
+            c.query_row(
+                "SELECT version, credential FROM lotp_credentials WHERE id = ?1",
+                params![id],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()
+        }) {
+            Ok(r) => row = r,
+            Err(e) => return Err(e),
+        }
+
+        match row {
+            Some((version, json)) => match serde_json::from_str(&json) {
+                Ok(credential) => {
+                    return Ok(Some(Record {
+                        version: version as u64,
+                        credential,
+                    }))
+                }
+                Err(e) => return Err(Error::Storage(e.to_string())),
+            },
+            None => return Ok(None),
+        }
+    }
+
+    #[allow(clippy::needless_late_init)]
+    fn insert(&self, id: &String, credential: &Credential) -> Result<bool, Error> {
+        let json;
+        match to_json(credential) {
+            Ok(j) => json = j,
+            Err(e) => return Err(e),
+        }
+
+        return self
+            .with_connection(|c| {
+                c.execute(
+                    "INSERT OR IGNORE INTO lotp_credentials (id, version, credential)
+                     VALUES (?1, 0, ?2)",
+                    params![id, json],
+                )
+            })
+            .map(|changed| changed == 1);
+    }
+
+    #[allow(clippy::needless_late_init)]
+    fn compare_and_swap(
+        &self,
+        id: &String,
+        version: &u64,
+        credential: &Credential,
+    ) -> Result<bool, Error> {
+        // Versions are stored as signed integers, which SQLite would turn into reals on overflow.
+        if *version >= i64::MAX as u64 {
+            return Err(super::version_exhausted());
+        }
+
+        let json;
+        match to_json(credential) {
+            Ok(j) => json = j,
+            Err(e) => return Err(e),
+        }
+
+        return self
+            .with_connection(|c| {
+                c.execute(
+                    "UPDATE lotp_credentials SET version = version + 1, credential = ?3
+                     WHERE id = ?1 AND version = ?2",
+                    params![id, *version as i64, json],
+                )
+            })
+            .map(|changed| changed == 1);
+    }
+
+    fn remove(&self, id: &String) -> Result<bool, Error> {
+        return self
+            .with_connection(|c| {
+                c.execute("DELETE FROM lotp_credentials WHERE id = ?1", params![id])
+            })
+            .map(|changed| changed == 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_sqlite_store() {
+        use super::*;
+        match Connection::open_in_memory() {
+            Ok(c) => match SqliteStore::from_connection(c) {
+                Ok(ref store) => crate::storage::check_store(store),
+                Err(e) => panic!("An error occurred when creating the store: {}", e),
+            },
+            Err(e) => panic!("An error occurred when opening the database: {}", e),
+        }
+    }
+}
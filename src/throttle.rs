@@ -0,0 +1,172 @@
+// Throttling of failed verifications, as required by RFC 4226 section 7.3.
+// A ThrottlePolicy decides how long a credential is locked out for after a number of consecutive
+// failures and the AttemptState records those failures so it can be persisted with the credential.
+#![allow(clippy::needless_return)]
+use crate::outcome::Outcome;
+
+// Decides when a credential should be locked out after failed verifications.
+pub trait ThrottlePolicy {
+    // Returns the unix time that the credential is locked until, or None if it isn't locked.
+    // failures - The amount of consecutive failures, including the one that just occurred.
+    // now - The current unix time in seconds.
+    fn lockout_until(&self, failures: &u32, now: &u64) -> Option<u64>;
+}
+
+// Locks the credential for a fixed amount of time after every max_failures consecutive failures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FixedLockout {
+    pub max_failures: u32, // The amount of failures allowed before the credential is locked.
+    pub lockout_secs: u64, // The amount of seconds that the credential is locked for.
+}
+
+impl ThrottlePolicy for FixedLockout {
+    fn lockout_until(&self, failures: &u32, now: &u64) -> Option<u64> {
+        if self.max_failures == 0 || !failures.is_multiple_of(self.max_failures) {
+            return None;
+        }
+
+        return Some(now.saturating_add(self.lockout_secs));
+    }
+}
+
+// Delays further attempts after every failure past free_failures, doubling the delay each time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExponentialBackoff {
+    pub free_failures: u32, // The amount of failures allowed before any delay is applied.
+    pub base_secs: u64,     // The delay after the first failure past free_failures.
+    pub max_secs: u64,      // The largest delay that will be applied.
+}
+
+impl ThrottlePolicy for ExponentialBackoff {
+    #[allow(clippy::needless_late_init)]
+    fn lockout_until(&self, failures: &u32, now: &u64) -> Option<u64> {
+        if failures <= &self.free_failures {
+            return None;
+        }
+
+        let exponent = failures - self.free_failures - 1;
+        let delay;
+        match 2u64.checked_pow(exponent) {
+            Some(factor) => delay = self.base_secs.saturating_mul(factor).min(self.max_secs),
+            None => delay = self.max_secs,
+        }
+
+        return Some(now.saturating_add(delay));
+    }
+}
+
+// The failed attempts for a single credential, this should be persisted with the credential.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AttemptState {
+    failures: u32,             // The amount of consecutive failed verifications.
+    locked_until: Option<u64>, // The unix time that the credential is locked until, if any.
+}
+
+impl AttemptState {
+    // Creates the state for a credential without any failed attempts.
+    pub fn new() -> AttemptState {
+        return AttemptState {
+            failures: 0,
+            locked_until: None,
+        };
+    }
+
+    // Creates the state from persisted values.
+    // failures - The amount of consecutive failed verifications.
+    // locked_until - The unix time that the credential is locked until, if any.
+    pub fn from_parts(failures: &u32, locked_until: &Option<u64>) -> AttemptState {
+        return AttemptState {
+            failures: *failures,
+            locked_until: *locked_until,
+        };
+    }
+
+    // Returns the amount of consecutive failed verifications.
+    pub fn failures(&self) -> u32 {
+        return self.failures;
+    }
+
+    // Returns the unix time the credential is locked until if it is locked at the time now.
+    // now - The current unix time in seconds.
+    pub fn locked_until(&self, now: &u64) -> Option<u64> {
+        match self.locked_until {
+            Some(until) if until > *now => return Some(until),
+            _ => return None,
+        }
+    }
+
+    // Records a failed verification and applies the policy.
+    // policy - The policy that decides whether the credential is now locked.
+    // now - The current unix time in seconds.
+    pub fn record_failure(&mut self, policy: &dyn ThrottlePolicy, now: &u64) {
+        self.failures = self.failures.saturating_add(1);
+        self.locked_until = policy.lockout_until(&self.failures, now);
+    }
+
+    // Records a successful verification, clearing any failures.
+    pub fn record_success(&mut self) {
+        self.failures = 0;
+        self.locked_until = None;
+    }
+
+    // Records the outcome of a throttled verification, replayed codes count as failures while
+    // malformed codes, which were never checked, are ignored.
+    // policy - The policy that decides whether the credential is now locked.
+    // now - The current unix time in seconds.
+    pub(crate) fn record_outcome(
+        &mut self,
+        policy: &dyn ThrottlePolicy,
+        now: &u64,
+        outcome: &Outcome,
+    ) {
+        match outcome {
+            Outcome::Valid { .. } => self.record_success(),
+            Outcome::Malformed => (),
+            _ => self.record_failure(policy, now),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_fixed_lockout() {
+        use super::*;
+        let policy = FixedLockout {
+            max_failures: 3,
+            lockout_secs: 60,
+        };
+        let mut state = AttemptState::new();
+
+        state.record_failure(&policy, &1000);
+        state.record_failure(&policy, &1000);
+        assert_eq!(state.locked_until(&1000), None);
+
+        state.record_failure(&policy, &1000);
+        assert_eq!(state.locked_until(&1000), Some(1060));
+        assert_eq!(state.locked_until(&1060), None);
+
+        state.record_success();
+        assert_eq!(state.failures(), 0);
+    }
+
+    #[test]
+    fn test_exponential_backoff() {
+        use super::*;
+        let policy = ExponentialBackoff {
+            free_failures: 1,
+            base_secs: 2,
+            max_secs: 10,
+        };
+
+        assert_eq!(policy.lockout_until(&1, &0), None);
+        assert_eq!(policy.lockout_until(&2, &0), Some(2));
+        assert_eq!(policy.lockout_until(&3, &0), Some(4));
+        assert_eq!(policy.lockout_until(&4, &0), Some(8));
+        assert_eq!(policy.lockout_until(&5, &0), Some(10));
+        assert_eq!(policy.lockout_until(&100, &0), Some(10));
+    }
+}
@@ -1,42 +1,117 @@
-use crate::error::{Error, ErrorType};
-use crate::hotp;
+#![allow(clippy::needless_return)]
+use crate::audit::{self, Auditor};
+use crate::backend::HmacKey;
+use crate::code::Code;
+use crate::error::Error;
+use crate::hotp::{self, Digits};
+use crate::outcome::Outcome;
+use alloc::string::String;
+#[cfg(feature = "std")]
 use std::time::{SystemTime, UNIX_EPOCH};
 
-// Check a TOTP code with 6 digits as a string.
+// Check a TOTP code against the time steps within offset of the current one, returning the
+// outcome.
 // secret - The secret used to generate the hash.
 // offset - The amount of codes in the future and past that are to be allowed(Recommended to be 1).
-// comparison - The code that is is to bee checked if valid.
+// comparison - The code that is is to be checked if valid.
 // duration_secs - The amount of time before a new code should be generated.
-pub fn check_6_digit_totp(
+// digits - The amount of digits in the code.
+// auditor - The observer the attempt is reported to, if any.
+#[cfg(feature = "std")]
+pub fn check_totp(
     secret: &String,
     offset: &u16,
     comparison: &String,
     duration_secs: &u64,
-) -> Result<bool, Error> {
-    let counter;
-
-    match SystemTime::now().duration_since(UNIX_EPOCH) {
-        Ok(c) => counter = c.as_secs() / duration_secs,
-        Err(_) => {
-            return Err(Error::new(
-                ErrorType::InvalidCounter,
-                "Could not calculate a value for the current counter.",
-            ))
+    digits: &Digits,
+    auditor: Option<&Auditor>,
+) -> Result<Outcome, Error> {
+    match unix_time() {
+        Ok(ref now) => {
+            return check_totp_at(
+                secret,
+                now,
+                offset,
+                comparison,
+                duration_secs,
+                digits,
+                auditor,
+            )
         }
+        Err(e) => return audit::report(auditor, None, Err(e), None),
     }
+}
 
-    if offset == &0u16 {
-        return hotp::check_6_digit_hotp(&counter, secret, &(*offset as u64), comparison);
+// Check a TOTP code like check_totp at the time now.
+// now - The unix time in seconds.
+// The remaining arguments are the same as check_totp.
+pub fn check_totp_at(
+    secret: &String,
+    now: &u64,
+    offset: &u16,
+    comparison: &String,
+    duration_secs: &u64,
+    digits: &Digits,
+    auditor: Option<&Auditor>,
+) -> Result<Outcome, Error> {
+    match hotp::decode_key(secret) {
+        Ok(ref key) => {
+            return check_totp_with_key(
+                key,
+                now,
+                offset,
+                comparison,
+                duration_secs,
+                digits,
+                auditor,
+            )
+        }
+        Err(e) => return audit::report(auditor, Some(*now), Err(e), None),
     }
+}
 
-    match duration_secs.checked_mul(*offset as u64) {
-        Some(ref n) => return hotp::check_6_digit_hotp(&counter, secret, n, comparison),
-        None => {
-            return Err(Error::new(
-                ErrorType::InvalidOffset,
-                "Either the duration provdided or the offset specified is too large.",
-            ))
-        }
+// Check a TOTP code at the time now like check_totp_at, using a key from any HMAC backend.
+// key - The key used to generate the hash.
+// The remaining arguments are the same as check_totp_at.
+pub fn check_totp_with_key<K: HmacKey + ?Sized>(
+    key: &K,
+    now: &u64,
+    offset: &u16,
+    comparison: &String,
+    duration_secs: &u64,
+    digits: &Digits,
+    auditor: Option<&Auditor>,
+) -> Result<Outcome, Error> {
+    let result = match counter_at(now, duration_secs) {
+        Ok(ref counter) => hotp::check_window(key, counter, &(*offset as u64), comparison, digits),
+        Err(e) => Err(e),
+    };
+
+    return audit::report(auditor, Some(*now), result, None);
+}
+
+// Check a TOTP code with 6 digits as a string.
+// secret - The secret used to generate the hash.
+// offset - The amount of codes in the future and past that are to be allowed(Recommended to be 1).
+// comparison - The code that is is to bee checked if valid.
+// duration_secs - The amount of time before a new code should be generated.
+#[cfg(feature = "std")]
+pub fn check_6_digit_totp(
+    secret: &String,
+    offset: &u16,
+    comparison: &String,
+    duration_secs: &u64,
+) -> Result<bool, Error> {
+    match check_totp(
+        secret,
+        offset,
+        comparison,
+        duration_secs,
+        &Digits::Six,
+        None,
+    ) {
+        Ok(o) => return Ok(o.is_valid()),
+        Err(e) => return Err(e),
     }
 }
 
@@ -45,36 +120,23 @@ pub fn check_6_digit_totp(
 // offset - The amount of codes in the future and past that are to be allowed(Recommended to be 1).
 // comparison - The code that is is to bee checked if valid.
 // duration_secs - The amount of time before a new code should be generated.
+#[cfg(feature = "std")]
 pub fn check_7_digit_totp(
     secret: &String,
     offset: &u16,
     comparison: &String,
     duration_secs: &u64,
 ) -> Result<bool, Error> {
-    let counter;
-
-    match SystemTime::now().duration_since(UNIX_EPOCH) {
-        Ok(c) => counter = c.as_secs() / duration_secs,
-        Err(_) => {
-            return Err(Error::new(
-                ErrorType::InvalidCounter,
-                "Could not calculate a value for the current counter.",
-            ))
-        }
-    }
-
-    if offset == &0u16 {
-        return hotp::check_7_digit_hotp(&counter, secret, &(*offset as u64), comparison);
-    }
-
-    match duration_secs.checked_mul(*offset as u64) {
-        Some(ref n) => return hotp::check_6_digit_hotp(&counter, secret, n, comparison),
-        None => {
-            return Err(Error::new(
-                ErrorType::InvalidOffset,
-                "Either the duration provdided or the offset specified is too large.",
-            ))
-        }
+    match check_totp(
+        secret,
+        offset,
+        comparison,
+        duration_secs,
+        &Digits::Seven,
+        None,
+    ) {
+        Ok(o) => return Ok(o.is_valid()),
+        Err(e) => return Err(e),
     }
 }
 
@@ -83,53 +145,67 @@ pub fn check_7_digit_totp(
 // offset - The amount of codes in the future and past that are to be allowed(Recommended to be 1).
 // comparison - The code that is is to bee checked if valid.
 // duration_secs - The amount of time before a new code should be generated.
+#[cfg(feature = "std")]
 pub fn check_8_digit_totp(
     secret: &String,
     offset: &u16,
     comparison: &String,
     duration_secs: &u64,
 ) -> Result<bool, Error> {
-    let counter;
+    match check_totp(
+        secret,
+        offset,
+        comparison,
+        duration_secs,
+        &Digits::Eight,
+        None,
+    ) {
+        Ok(o) => return Ok(o.is_valid()),
+        Err(e) => return Err(e),
+    }
+}
 
-    match SystemTime::now().duration_since(UNIX_EPOCH) {
-        Ok(c) => counter = c.as_secs() / duration_secs,
-        Err(_) => {
-            return Err(Error::new(
-                ErrorType::InvalidCounter,
-                "Could not calculate a value for the current counter.",
-            ))
-        }
+// Calculate the current TOTP counter (time step) from the time since the UNIX epoch.
+// duration_secs - The amount of seconds that each code is valid for.
+#[cfg(feature = "std")]
+pub fn current_counter(duration_secs: &u64) -> Result<u64, Error> {
+    match unix_time() {
+        Ok(ref time) => return counter_at(time, duration_secs),
+        Err(e) => return Err(e),
     }
+}
 
-    if offset == &0u16 {
-        return hotp::check_8_digit_hotp(&counter, secret, &(*offset as u64), comparison);
+// Returns the amount of seconds since the UNIX epoch.
+#[cfg(feature = "std")]
+pub fn unix_time() -> Result<u64, Error> {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(c) => return Ok(c.as_secs()),
+        Err(_) => return Err(Error::ClockBeforeEpoch),
     }
+}
 
-    match duration_secs.checked_mul(*offset as u64) {
-        Some(ref n) => return hotp::check_6_digit_hotp(&counter, secret, n, comparison),
-        None => {
-            return Err(Error::new(
-                ErrorType::InvalidOffset,
-                "Either the duration provdided or the offset specified is too large.",
-            ))
-        }
+// Calculate the TOTP counter (time step) for a unix time.
+// time - The unix time in seconds.
+// duration_secs - The amount of seconds that each code is valid for.
+pub fn counter_at(time: &u64, duration_secs: &u64) -> Result<u64, Error> {
+    if duration_secs == &0 {
+        return Err(Error::InvalidPeriod(*duration_secs));
     }
+
+    return Ok(time / duration_secs);
 }
 
 // Generate a 6 digit TOTP code using the time since the UNIX epoch.
 // secret - The secret used to generate the hash in base-32.
 // duration_secs - The amount of seconds that the code should be valid for.
+#[cfg(feature = "std")]
+#[allow(clippy::needless_late_init)]
 pub fn generate_6_digit_totp(secret: &String, duration_secs: &u64) -> Result<u64, Error> {
     let counter;
 
-    match SystemTime::now().duration_since(UNIX_EPOCH) {
-        Ok(c) => counter = c.as_secs() / duration_secs,
-        Err(_) => {
-            return Err(Error::new(
-                ErrorType::InvalidCounter,
-                "Could not calculate a value for the current counter.",
-            ))
-        }
+    match current_counter(duration_secs) {
+        Ok(c) => counter = c,
+        Err(e) => return Err(e),
     }
 
     return hotp::generate_6_digit_hotp(&counter, secret);
@@ -138,17 +214,14 @@ pub fn generate_6_digit_totp(secret: &String, duration_secs: &u64) -> Result<u64
 // Generate a 7 digit TOTP code using the time since the UNIX epoch.
 // secret - The secret used to generate the hash in base-32.
 // duration_secs - The amount of seconds that the code should be valid for.
+#[cfg(feature = "std")]
+#[allow(clippy::needless_late_init)]
 pub fn generate_7_digit_totp(secret: &String, duration_secs: &u64) -> Result<u64, Error> {
     let counter;
 
-    match SystemTime::now().duration_since(UNIX_EPOCH) {
-        Ok(c) => counter = c.as_secs() / duration_secs,
-        Err(_) => {
-            return Err(Error::new(
-                ErrorType::InvalidCounter,
-                "Could not calculate a value for the current counter.",
-            ))
-        }
+    match current_counter(duration_secs) {
+        Ok(c) => counter = c,
+        Err(e) => return Err(e),
     }
 
     return hotp::generate_7_digit_hotp(&counter, secret);
@@ -157,17 +230,14 @@ pub fn generate_7_digit_totp(secret: &String, duration_secs: &u64) -> Result<u64
 // Generate a 8 digit TOTP code using the time since the UNIX epoch.
 // secret - The secret used to generate the hash in base-32.
 // duration_secs - The amount of seconds that the code should be valid for.
+#[cfg(feature = "std")]
+#[allow(clippy::needless_late_init)]
 pub fn generate_8_digit_totp(secret: &String, duration_secs: &u64) -> Result<u64, Error> {
     let counter;
 
-    match SystemTime::now().duration_since(UNIX_EPOCH) {
-        Ok(c) => counter = c.as_secs() / duration_secs,
-        Err(_) => {
-            return Err(Error::new(
-                ErrorType::InvalidCounter,
-                "Could not calculate a value for the current counter.",
-            ))
-        }
+    match current_counter(duration_secs) {
+        Ok(c) => counter = c,
+        Err(e) => return Err(e),
     }
 
     return hotp::generate_8_digit_hotp(&counter, secret);
@@ -176,16 +246,10 @@ pub fn generate_8_digit_totp(secret: &String, duration_secs: &u64) -> Result<u64
 // Generate a 6 digit TOTP code using the time since the UNIX epoch. Returns a string instead of a number
 // secret - The secret used to generate the hash in base-32.
 // duration_secs - The amount of seconds that the code should be valid for.
+#[cfg(feature = "std")]
 pub fn generate_6_digit_totp_string(secret: &String, duration_secs: &u64) -> Result<String, Error> {
     match generate_6_digit_totp(secret, duration_secs) {
-        Ok(n) => {
-            let mut string: String = format!("{}", n);
-            while string.len() < 6 {
-                string.insert(0, '0');
-            }
-
-            return Ok(string);
-        }
+        Ok(n) => return Ok(hotp::format_code(&n, &Digits::Six)),
         Err(e) => return Err(e),
     }
 }
@@ -193,16 +257,10 @@ pub fn generate_6_digit_totp_string(secret: &String, duration_secs: &u64) -> Res
 // Generate a 7 digit TOTP code using the time since the UNIX epoch. Returns a string instead of a number
 // secret - The secret used to generate the hash in base-32.
 // duration_secs - The amount of seconds that the code should be valid for.
+#[cfg(feature = "std")]
 pub fn generate_7_digit_totp_string(secret: &String, duration_secs: &u64) -> Result<String, Error> {
     match generate_7_digit_totp(secret, duration_secs) {
-        Ok(n) => {
-            let mut string: String = format!("{}", n);
-            while string.len() < 7 {
-                string.insert(0, '0');
-            }
-
-            return Ok(string);
-        }
+        Ok(n) => return Ok(hotp::format_code(&n, &Digits::Seven)),
         Err(e) => return Err(e),
     }
 }
@@ -210,16 +268,65 @@ pub fn generate_7_digit_totp_string(secret: &String, duration_secs: &u64) -> Res
 // Generate a 8 digit TOTP code using the time since the UNIX epoch. Returns a string instead of a number
 // secret - The secret used to generate the hash in base-32.
 // duration_secs - The amount of seconds that the code should be valid for.
+#[cfg(feature = "std")]
 pub fn generate_8_digit_totp_string(secret: &String, duration_secs: &u64) -> Result<String, Error> {
     match generate_8_digit_totp(secret, duration_secs) {
-        Ok(n) => {
-            let mut string: String = format!("{}", n);
-            while string.len() < 8 {
-                string.insert(0, '0');
-            }
+        Ok(n) => return Ok(hotp::format_code(&n, &Digits::Eight)),
+        Err(e) => return Err(e),
+    }
+}
 
-            return Ok(string);
-        }
+// Generate a TOTP code with the specified amount of digits using the time since the UNIX epoch,
+// without allocating for secrets of up to 128 bytes.
+// secret - The secret used to generate the hash in base-32.
+// duration_secs - The amount of seconds that the code should be valid for.
+// digits - The amount of digits in the code.
+#[cfg(feature = "std")]
+#[allow(clippy::needless_late_init)]
+pub fn generate_totp_code(
+    secret: &String,
+    duration_secs: &u64,
+    digits: &Digits,
+) -> Result<Code, Error> {
+    let counter;
+
+    match current_counter(duration_secs) {
+        Ok(c) => counter = c,
+        Err(e) => return Err(e),
+    }
+
+    return hotp::generate_hotp_code(&counter, secret, digits);
+}
+
+// Generate a TOTP code like generate_totp_code at the time now.
+// secret - The secret used to generate the hash in base-32.
+// now - The unix time in seconds.
+// duration_secs - The amount of seconds that the code should be valid for.
+// digits - The amount of digits in the code.
+pub fn generate_totp_code_at(
+    secret: &String,
+    now: &u64,
+    duration_secs: &u64,
+    digits: &Digits,
+) -> Result<Code, Error> {
+    match counter_at(now, duration_secs) {
+        Ok(ref counter) => return hotp::generate_hotp_code(counter, secret, digits),
+        Err(e) => return Err(e),
+    }
+}
+
+// Generate a TOTP code at the time now like generate_totp_code_at, using a key from any HMAC
+// backend.
+// key - The key used to generate the hash.
+// The remaining arguments are the same as generate_totp_code_at.
+pub fn generate_totp_code_with_key<K: HmacKey + ?Sized>(
+    key: &K,
+    now: &u64,
+    duration_secs: &u64,
+    digits: &Digits,
+) -> Result<Code, Error> {
+    match counter_at(now, duration_secs) {
+        Ok(ref counter) => return hotp::generate_hotp_code_with_key(key, counter, digits),
         Err(e) => return Err(e),
     }
 }
@@ -227,74 +334,117 @@ pub fn generate_8_digit_totp_string(secret: &String, duration_secs: &u64) -> Res
 #[cfg(test)]
 mod tests {
     #[test]
+    #[cfg(feature = "std")]
     fn test_generate_6_digit_totp() {
         use super::*;
         match generate_6_digit_totp(&String::from("abcdef234567abcdef234567"), &30) {
             Ok(_) => (),
-            Err(e) => panic!(
-                "An error occurred when generating the hotp: {}",
-                e.description()
-            ),
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
         }
     }
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_generate_7_digit_totp() {
         use super::*;
         match generate_7_digit_totp(&String::from("abcdef234567abcdef234567"), &30) {
             Ok(_) => (),
-            Err(e) => panic!(
-                "An error occurred when generating the hotp: {}",
-                e.description()
-            ),
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
         }
     }
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_generate_8_digit_totp() {
         use super::*;
         match generate_8_digit_totp(&String::from("abcdef234567abcdef234567"), &30) {
             Ok(_) => (),
-            Err(e) => panic!(
-                "An error occurred when generating the hotp: {}",
-                e.description()
-            ),
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
         }
     }
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_generate_6_digit_totp_string() {
         use super::*;
         match generate_6_digit_totp_string(&String::from("abcdef234567abcdef234567"), &30) {
             Ok(c) => assert_eq!(c.len(), 6),
-            Err(e) => panic!(
-                "An error occurred when generating the hotp: {}",
-                e.description()
-            ),
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
         }
     }
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_generate_7_digit_totp_string() {
         use super::*;
         match generate_7_digit_totp_string(&String::from("abcdef234567abcdef234567"), &30) {
             Ok(c) => assert_eq!(c.len(), 7),
-            Err(e) => panic!(
-                "An error occurred when generating the hotp: {}",
-                e.description()
-            ),
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
         }
     }
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_generate_8_digit_totp_string() {
         use super::*;
         match generate_8_digit_totp_string(&String::from("abcdef234567abcdef234567"), &30) {
             Ok(c) => assert_eq!(c.len(), 8),
-            Err(e) => panic!(
-                "An error occurred when generating the hotp: {}",
-                e.description()
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_check_8_digit_totp() {
+        use super::*;
+        let secret = String::from("abcdef234567abcdef234567");
+        match generate_8_digit_totp_string(&secret, &30) {
+            Ok(ref c) => match check_8_digit_totp(&secret, &1, c, &30) {
+                Ok(b) => assert!(b),
+                Err(e) => panic!("An error occurred when checking the totp: {}", e),
+            },
+            Err(e) => panic!("An error occurred when generating the totp: {}", e),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_invalid_period() {
+        use super::*;
+        match generate_6_digit_totp(&String::from("abcdef234567abcdef234567"), &0) {
+            Ok(_) => panic!("A period of zero should not generate a code."),
+            Err(e) => assert_eq!(e, Error::InvalidPeriod(0)),
+        }
+    }
+
+    #[test]
+    fn test_totp_at() {
+        use super::*;
+        // RFC 6238 appendix B.
+        let secret = String::from("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        match generate_totp_code_at(&secret, &1111111109, &30, &Digits::Eight) {
+            Ok(c) => assert_eq!(c.as_str(), "07081804"),
+            Err(e) => panic!("An error occurred when generating the totp: {}", e),
+        }
+
+        let key = match hotp::decode_key(&secret) {
+            Ok(k) => k,
+            Err(e) => panic!("An error occurred when decoding the secret: {}", e),
+        };
+        let code = String::from("14050471");
+        match check_totp_with_key(&key, &1111111141, &1, &code, &30, &Digits::Eight, None) {
+            Ok(o) => assert_eq!(
+                o,
+                Outcome::Valid {
+                    counter: 37037037,
+                    step_delta: -1,
+                }
             ),
+            Err(e) => panic!("An error occurred when checking the totp: {}", e),
         }
+        assert_eq!(
+            check_totp_at(&secret, &1111111141, &0, &code, &30, &Digits::Eight, None),
+            Ok(Outcome::Invalid)
+        );
     }
 }
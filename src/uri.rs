@@ -0,0 +1,284 @@
+// Provisioning URIs in the otpauth Key Uri Format used by authenticator apps, for example
+// otpauth://totp/Example:alice@example.com?secret=JBSWY3DPEHPK3PXP&issuer=Example
+#![allow(clippy::needless_return)]
+use crate::error::Error;
+use crate::hotp::{Algorithm, Digits};
+use crate::otp::{Credential, Hotp, Totp};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+// A credential parsed from an otpauth URI along with its label. The derived Debug uses the
+// credential's, which never prints the secret.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProvisioningUri {
+    pub credential: Credential, // The credential described by the URI.
+    pub issuer: Option<String>, // The provider or service the credential belongs to, if given.
+    pub account: String,        // The account name, usually the user's email address.
+}
+
+// Build an otpauth URI for a credential.
+// credential - The credential, its secret is included in the URI.
+// issuer - The provider or service the credential belongs to.
+// account - The account name, usually the user's email address.
+#[allow(clippy::ptr_arg)]
+pub fn credential_uri(credential: &Credential, issuer: &String, account: &String) -> String {
+    let mut uri = String::from("otpauth://");
+    let secret;
+    let digits;
+    let algorithm;
+
+    match credential {
+        Credential::Hotp(c) => {
+            uri.push_str("hotp/");
+            secret = &c.secret;
+            digits = c.digits;
+            algorithm = c.algorithm;
+        }
+        Credential::Totp(c) => {
+            uri.push_str("totp/");
+            secret = &c.secret;
+            digits = c.digits;
+            algorithm = c.algorithm;
+        }
+    }
+
+    uri.push_str(&encode(issuer));
+    uri.push(':');
+    uri.push_str(&encode(account));
+    uri.push_str("?secret=");
+    uri.push_str(&encode(&secret.trim_end_matches('=').to_ascii_uppercase()));
+    uri.push_str("&issuer=");
+    uri.push_str(&encode(issuer));
+    uri.push_str("&algorithm=");
+    uri.push_str(algorithm_name(&algorithm));
+    uri.push_str("&digits=");
+    uri.push_str(&digits.count().to_string());
+
+    match credential {
+        Credential::Hotp(c) => {
+            uri.push_str("&counter=");
+            uri.push_str(&c.counter.to_string());
+        }
+        Credential::Totp(c) => {
+            uri.push_str("&period=");
+            uri.push_str(&c.period.to_string());
+        }
+    }
+
+    return uri;
+}
+
+// Parse an otpauth URI into a credential. Parameters that aren't given take the defaults used by
+// Hotp::new and Totp::new, unknown parameters are ignored.
+// uri - The otpauth URI.
+#[allow(clippy::needless_late_init, clippy::ptr_arg)]
+pub fn parse_uri(uri: &String) -> Result<ProvisioningUri, Error> {
+    let rest;
+    match uri.strip_prefix("otpauth://") {
+        Some(r) => rest = r,
+        None => return Err(invalid("The scheme must be otpauth.")),
+    }
+
+    let (kind, rest) = rest.split_once('/').unwrap_or((rest, ""));
+    let (raw_label, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+    let label;
+    match decode(raw_label) {
+        Ok(l) => label = l,
+        Err(e) => return Err(e),
+    }
+
+    let mut issuer = None;
+    let account;
+    match label.split_once(':') {
+        Some((i, a)) => {
+            issuer = Some(String::from(i));
+            account = String::from(a.trim_start());
+        }
+        None => account = label,
+    }
+
+    let mut secret_param = None;
+    let mut algorithm = Algorithm::Sha1;
+    let mut digits = Digits::Six;
+    let mut period = None;
+    let mut counter = None;
+
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, raw) = pair.split_once('=').unwrap_or((pair, ""));
+        let value;
+        match decode(raw) {
+            Ok(v) => value = v,
+            Err(e) => return Err(e),
+        }
+
+        match key {
+            "secret" => secret_param = Some(value),
+            "issuer" => issuer = Some(value),
+            "algorithm" => match value.to_ascii_uppercase().as_str() {
+                "SHA1" => algorithm = Algorithm::Sha1,
+                "SHA256" => algorithm = Algorithm::Sha256,
+                "SHA512" => algorithm = Algorithm::Sha512,
+                _ => return Err(invalid("The algorithm is not supported.")),
+            },
+            "digits" => match value.parse::<u32>() {
+                Ok(d) => match Digits::try_from(d) {
+                    Ok(d) => digits = d,
+                    Err(e) => return Err(e),
+                },
+                Err(_) => return Err(invalid("The digits parameter is not a number.")),
+            },
+            "period" => match value.parse::<u64>() {
+                Ok(0) => return Err(Error::InvalidPeriod(0)),
+                Ok(p) => period = Some(p),
+                Err(_) => return Err(invalid("The period parameter is not a number.")),
+            },
+            "counter" => match value.parse::<u64>() {
+                Ok(c) => counter = Some(c),
+                Err(_) => return Err(invalid("The counter parameter is not a number.")),
+            },
+            _ => (),
+        }
+    }
+
+    let secret;
+    match secret_param {
+        Some(s) if !s.is_empty() => secret = s,
+        _ => return Err(invalid("The secret parameter is missing.")),
+    }
+
+    let credential;
+    match kind {
+        "hotp" => {
+            let c;
+            match counter {
+                Some(v) => c = v,
+                None => return Err(invalid("The counter parameter is required for hotp.")),
+            }
+
+            let mut hotp = Hotp::new(&secret, &c);
+            hotp.digits = digits;
+            hotp.algorithm = algorithm;
+            credential = Credential::Hotp(hotp);
+        }
+        "totp" => {
+            let mut totp = Totp::new(&secret);
+            totp.digits = digits;
+            totp.algorithm = algorithm;
+            if let Some(p) = period {
+                totp.period = p;
+            }
+            credential = Credential::Totp(totp);
+        }
+        _ => return Err(invalid("The type must be hotp or totp.")),
+    }
+
+    return Ok(ProvisioningUri {
+        credential,
+        issuer,
+        account,
+    });
+}
+
+fn invalid(reason: &str) -> Error {
+    return Error::InvalidUri(String::from(reason));
+}
+
+fn algorithm_name(algorithm: &Algorithm) -> &'static str {
+    match algorithm {
+        Algorithm::Sha1 => return "SHA1",
+        Algorithm::Sha256 => return "SHA256",
+        Algorithm::Sha512 => return "SHA512",
+    }
+}
+
+// Percent-encode everything except the unreserved characters of RFC 3986.
+fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+
+    for b in value.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+
+    return encoded;
+}
+
+fn decode(value: &str) -> Result<String, Error> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            decoded.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+
+        match value.get(i + 1..i + 3).map(|h| u8::from_str_radix(h, 16)) {
+            Some(Ok(b)) => decoded.push(b),
+            _ => return Err(invalid("The URI contains an invalid percent-encoding.")),
+        }
+        i += 3;
+    }
+
+    match String::from_utf8(decoded) {
+        Ok(s) => return Ok(s),
+        Err(_) => return Err(invalid("The URI is not valid UTF-8 once decoded.")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_uri_round_trip() {
+        use super::*;
+        let mut totp = Totp::new(&String::from("JBSWY3DPEHPK3PXP"));
+        totp.digits = Digits::Eight;
+        totp.algorithm = Algorithm::Sha256;
+        totp.period = 60;
+
+        let credential = Credential::Totp(totp);
+        let uri = credential_uri(
+            &credential,
+            &String::from("Example Co"),
+            &String::from("alice@example.com"),
+        );
+        assert_eq!(
+            uri,
+            "otpauth://totp/Example%20Co:alice%40example.com?secret=JBSWY3DPEHPK3PXP\
+             &issuer=Example%20Co&algorithm=SHA256&digits=8&period=60"
+        );
+
+        match parse_uri(&uri) {
+            Ok(parsed) => {
+                assert_eq!(parsed.credential, credential);
+                assert_eq!(parsed.issuer, Some(String::from("Example Co")));
+                assert_eq!(parsed.account, "alice@example.com");
+                assert!(!format!("{:?}", parsed).contains("JBSWY3DPEHPK3PXP"));
+            }
+            Err(e) => panic!("An error occurred when parsing the uri: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_parse_uri_errors() {
+        use super::*;
+        let cases = [
+            "https://totp/a?secret=JBSWY3DPEHPK3PXP",
+            "otpauth://totp/a?issuer=b",
+            "otpauth://hotp/a?secret=JBSWY3DPEHPK3PXP",
+            "otpauth://totp/a?secret=JBSWY3DPEHPK3PXP&digits=9",
+            "otpauth://totp/a%2?secret=JBSWY3DPEHPK3PXP",
+        ];
+
+        for case in cases {
+            assert!(parse_uri(&String::from(case)).is_err(), "{}", case);
+        }
+    }
+}
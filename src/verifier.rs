@@ -0,0 +1,586 @@
+#![allow(clippy::needless_return)]
+use crate::audit::{self, Auditor};
+use crate::backend::{self, HmacKey};
+use crate::drift::{self, DriftState};
+use crate::error::Error;
+use crate::hotp::{self, Digits};
+use crate::outcome::{self, Outcome};
+use crate::throttle::{AttemptState, ThrottlePolicy};
+use crate::totp;
+use alloc::string::String;
+
+// Tracks the last accepted HOTP counter or TOTP time step for a single credential, so that a
+// code cannot be accepted twice (RFC 4226 section 7.2 and RFC 6238 section 5.2).
+// One Verifier should be kept per credential and its state persisted after each accepted code.
+// For TOTP the verifier also tracks the credential's clock drift, centring windows on it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Verifier {
+    last_counter: Option<u64>, // The last counter that was accepted, if any.
+    attempts: AttemptState,    // The failed attempts, used by the throttled verifications.
+    drift: DriftState,         // The clock drift of a TOTP credential.
+}
+
+impl Verifier {
+    // Creates a verifier for a credential that has never had a code accepted.
+    pub fn new() -> Verifier {
+        return Verifier {
+            last_counter: None,
+            attempts: AttemptState::new(),
+            drift: DriftState::new(),
+        };
+    }
+
+    // Creates a verifier from persisted state.
+    // last_counter - The last HOTP counter or TOTP time step that was accepted.
+    pub fn from_last_counter(last_counter: &u64) -> Verifier {
+        return Verifier {
+            last_counter: Some(*last_counter),
+            attempts: AttemptState::new(),
+            drift: DriftState::new(),
+        };
+    }
+
+    // Creates a verifier from persisted state, including the failed attempts and clock drift.
+    // last_counter - The last HOTP counter or TOTP time step that was accepted, if any.
+    // attempts - The failed attempts for the credential.
+    // drift - The clock drift of the credential, DriftState::new() for HOTP credentials.
+    pub fn from_state(
+        last_counter: &Option<u64>,
+        attempts: &AttemptState,
+        drift: &DriftState,
+    ) -> Verifier {
+        return Verifier {
+            last_counter: *last_counter,
+            attempts: *attempts,
+            drift: *drift,
+        };
+    }
+
+    // Returns the last accepted counter, this should be persisted after every accepted code.
+    pub fn last_counter(&self) -> Option<u64> {
+        return self.last_counter;
+    }
+
+    // Returns the failed attempts, this should be persisted after every throttled verification.
+    pub fn attempts(&self) -> AttemptState {
+        return self.attempts;
+    }
+
+    // Returns the clock drift, this should be persisted after every accepted TOTP code.
+    pub fn drift(&self) -> DriftState {
+        return self.drift;
+    }
+
+    // Check a HOTP code, accepting it only if it matches a counter after the last accepted one.
+    // counter - the value of the hotp counter.
+    // secret - The secret used to generate the hash.
+    // offset - The value added and subtracted from the counter that are considered valid.
+    // comparison - The code that is is to be checked if valid.
+    // digits - The amount of digits in the code.
+    // auditor - The observer the attempt is reported to, if any.
+    pub fn verify_hotp(
+        &mut self,
+        counter: &u64,
+        secret: &String,
+        offset: &u64,
+        comparison: &String,
+        digits: &Digits,
+        auditor: Option<&Auditor>,
+    ) -> Result<Outcome, Error> {
+        match hotp::decode_key(secret) {
+            Ok(ref key) => {
+                return self.verify_hotp_with_key(key, counter, offset, comparison, digits, auditor)
+            }
+            Err(e) => return audit::report(auditor, None, Err(e), Some(self.attempts)),
+        }
+    }
+
+    // Check a HOTP code like verify_hotp, using a key from any HMAC backend.
+    // key - The key used to generate the hash.
+    // The remaining arguments are the same as verify_hotp.
+    pub fn verify_hotp_with_key<K: HmacKey + ?Sized>(
+        &mut self,
+        key: &K,
+        counter: &u64,
+        offset: &u64,
+        comparison: &String,
+        digits: &Digits,
+        auditor: Option<&Auditor>,
+    ) -> Result<Outcome, Error> {
+        let result = self.verify_window(key, counter, offset, comparison, digits);
+        return audit::report(auditor, None, result, Some(self.attempts));
+    }
+
+    // Check a TOTP code, accepting it only if it matches a time step after the last accepted one.
+    // The window is centred on the current time step adjusted by the credential's clock drift.
+    // secret - The secret used to generate the hash.
+    // offset - The amount of codes in the future and past that are to be allowed(Recommended to be 1).
+    // comparison - The code that is is to be checked if valid.
+    // duration_secs - The amount of time before a new code should be generated.
+    // digits - The amount of digits in the code.
+    // auditor - The observer the attempt is reported to, if any.
+    #[cfg(feature = "std")]
+    #[allow(clippy::needless_late_init)]
+    pub fn verify_totp(
+        &mut self,
+        secret: &String,
+        offset: &u16,
+        comparison: &String,
+        duration_secs: &u64,
+        digits: &Digits,
+        auditor: Option<&Auditor>,
+    ) -> Result<Outcome, Error> {
+        let now;
+        match totp::unix_time() {
+            Ok(t) => now = t,
+            Err(e) => return audit::report(auditor, None, Err(e), Some(self.attempts)),
+        }
+
+        match hotp::decode_key(secret) {
+            Ok(ref key) => {
+                return self.verify_totp_with_key(
+                    key,
+                    &now,
+                    offset,
+                    comparison,
+                    duration_secs,
+                    digits,
+                    auditor,
+                )
+            }
+            Err(e) => return audit::report(auditor, Some(now), Err(e), Some(self.attempts)),
+        }
+    }
+
+    // Check a TOTP code at the time now like verify_totp, using a key from any HMAC backend.
+    // key - The key used to generate the hash.
+    // now - The current unix time in seconds.
+    // The remaining arguments are the same as verify_totp.
+    #[allow(clippy::too_many_arguments)]
+    pub fn verify_totp_with_key<K: HmacKey + ?Sized>(
+        &mut self,
+        key: &K,
+        now: &u64,
+        offset: &u16,
+        comparison: &String,
+        duration_secs: &u64,
+        digits: &Digits,
+        auditor: Option<&Auditor>,
+    ) -> Result<Outcome, Error> {
+        let result = match totp::counter_at(now, duration_secs) {
+            Ok(ref counter) => self.verify_step(key, counter, offset, comparison, digits),
+            Err(e) => Err(e),
+        };
+
+        return audit::report(auditor, Some(*now), result, Some(self.attempts));
+    }
+
+    // Check a HOTP code like verify_hotp, refusing to check it while the credential is locked out
+    // and recording the result with the throttling policy.
+    // policy - The policy that decides when the credential is locked out.
+    // now - The current unix time in seconds.
+    // The remaining arguments are the same as verify_hotp, the event reports the lockout state
+    // after the attempt.
+    #[allow(clippy::too_many_arguments)]
+    pub fn verify_hotp_throttled(
+        &mut self,
+        policy: &dyn ThrottlePolicy,
+        now: &u64,
+        counter: &u64,
+        secret: &String,
+        offset: &u64,
+        comparison: &String,
+        digits: &Digits,
+        auditor: Option<&Auditor>,
+    ) -> Result<Outcome, Error> {
+        let result = self.hotp_throttled(policy, now, counter, secret, offset, comparison, digits);
+        return audit::report(auditor, Some(*now), result, Some(self.attempts));
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn hotp_throttled(
+        &mut self,
+        policy: &dyn ThrottlePolicy,
+        now: &u64,
+        counter: &u64,
+        secret: &String,
+        offset: &u64,
+        comparison: &String,
+        digits: &Digits,
+    ) -> Result<Outcome, Error> {
+        if let Some(until) = self.attempts.locked_until(now) {
+            return Ok(Outcome::LockedOut { until });
+        }
+
+        match hotp::decode_key(secret) {
+            Ok(ref key) => match self.verify_window(key, counter, offset, comparison, digits) {
+                Ok(outcome) => return Ok(self.record_attempt(policy, now, outcome)),
+                Err(e) => return Err(e),
+            },
+            Err(e) => return Err(e),
+        }
+    }
+
+    // Check a TOTP code at the time now like verify_totp, refusing to check it while the
+    // credential is locked out and recording the result with the throttling policy.
+    // policy - The policy that decides when the credential is locked out.
+    // now - The current unix time in seconds, also used to calculate the time step.
+    // The remaining arguments are the same as verify_totp, the event reports the lockout state
+    // after the attempt.
+    #[allow(clippy::too_many_arguments)]
+    pub fn verify_totp_throttled(
+        &mut self,
+        policy: &dyn ThrottlePolicy,
+        now: &u64,
+        secret: &String,
+        offset: &u16,
+        comparison: &String,
+        duration_secs: &u64,
+        digits: &Digits,
+        auditor: Option<&Auditor>,
+    ) -> Result<Outcome, Error> {
+        let result = self.totp_throttled(
+            policy,
+            now,
+            secret,
+            offset,
+            comparison,
+            duration_secs,
+            digits,
+        );
+        return audit::report(auditor, Some(*now), result, Some(self.attempts));
+    }
+
+    #[allow(clippy::needless_late_init, clippy::too_many_arguments)]
+    fn totp_throttled(
+        &mut self,
+        policy: &dyn ThrottlePolicy,
+        now: &u64,
+        secret: &String,
+        offset: &u16,
+        comparison: &String,
+        duration_secs: &u64,
+        digits: &Digits,
+    ) -> Result<Outcome, Error> {
+        if let Some(until) = self.attempts.locked_until(now) {
+            return Ok(Outcome::LockedOut { until });
+        }
+
+        let counter;
+        match totp::counter_at(now, duration_secs) {
+            Ok(c) => counter = c,
+            Err(e) => return Err(e),
+        }
+
+        match hotp::decode_key(secret) {
+            Ok(ref key) => match self.verify_step(key, &counter, offset, comparison, digits) {
+                Ok(outcome) => return Ok(self.record_attempt(policy, now, outcome)),
+                Err(e) => return Err(e),
+            },
+            Err(e) => return Err(e),
+        }
+    }
+
+    // Records the outcome of a throttled verification in the failed attempts.
+    pub(crate) fn record_attempt(
+        &mut self,
+        policy: &dyn ThrottlePolicy,
+        now: &u64,
+        outcome: Outcome,
+    ) -> Outcome {
+        self.attempts.record_outcome(policy, now, &outcome);
+        return outcome;
+    }
+
+    // Check a TOTP code against a window centred on the drift adjusted time step, recording the
+    // drift if the code is accepted. The drift is bounded to MAX_DRIFT_WINDOWS times the offset.
+    // key - The decoded secret.
+    // counter - The current time step.
+    pub(crate) fn verify_step<K: HmacKey + ?Sized>(
+        &mut self,
+        key: &K,
+        counter: &u64,
+        offset: &u16,
+        comparison: &String,
+        digits: &Digits,
+    ) -> Result<Outcome, Error> {
+        // The estimate may have been recorded with a wider window, so the centre is clamped too.
+        let max_steps = (*offset as u64).saturating_mul(drift::MAX_DRIFT_WINDOWS);
+        let bound = max_steps as i64;
+        let centre = counter.saturating_add_signed(self.drift.centre().clamp(-bound, bound));
+
+        match self.verify_window(key, &centre, &(*offset as u64), comparison, digits) {
+            Ok(Outcome::Valid { counter: step, .. }) => {
+                let step_delta = outcome::step_delta(&step, counter);
+                self.drift.record(&step_delta, &max_steps);
+                return Ok(Outcome::Valid {
+                    counter: step,
+                    step_delta,
+                });
+            }
+            o => return o,
+        }
+    }
+
+    // Check a code against the counters within offset of the counter, rejecting matches at or
+    // before the last accepted counter as replays.
+    // key - The decoded secret.
+    #[allow(clippy::needless_late_init)]
+    pub(crate) fn verify_window<K: HmacKey + ?Sized>(
+        &mut self,
+        key: &K,
+        counter: &u64,
+        offset: &u64,
+        comparison: &String,
+        digits: &Digits,
+    ) -> Result<Outcome, Error> {
+        let code;
+        match outcome::parse_code(comparison, digits) {
+            Some(c) => code = c,
+            None => return Ok(Outcome::Malformed),
+        }
+
+        let min = counter.saturating_sub(*offset);
+        let max = counter.saturating_add(*offset);
+        let mut matched = None;
+        let mut replayed = false;
+
+        // Every counter in the window is computed and compared in constant time, so the time taken
+        // doesn't reveal where the code matched and a match before the last accepted counter
+        // doesn't hide a later, valid match.
+        for i in min..=max {
+            match hotp::truncated_hotp(key, &i) {
+                Ok(value) if backend::codes_equal(&(value % digits.modulus()), &code) => (),
+                Ok(_) => continue,
+                Err(e) => return Err(e),
+            }
+
+            match self.last_counter {
+                Some(last) if i <= last => replayed = true,
+                _ if matched.is_none() => matched = Some(i),
+                _ => (),
+            }
+        }
+
+        if let Some(i) = matched {
+            self.last_counter = Some(i);
+            return Ok(Outcome::Valid {
+                counter: i,
+                step_delta: outcome::step_delta(&i, counter),
+            });
+        } else if replayed {
+            return Ok(Outcome::Replayed);
+        }
+
+        return Ok(Outcome::Invalid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_verify_hotp_valid() {
+        use super::*;
+        let secret = String::from("abcdef234567");
+        let code = match hotp::generate_6_digit_hotp_string(&400u64, &secret) {
+            Ok(s) => s,
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
+        };
+        let mut verifier = Verifier::new();
+
+        match verifier.verify_hotp(&398u64, &secret, &3u64, &code, &Digits::Six, None) {
+            Ok(v) => assert_eq!(
+                v,
+                Outcome::Valid {
+                    counter: 400,
+                    step_delta: 2,
+                }
+            ),
+            Err(e) => panic!("An error occurred when verifying the hotp: {}", e),
+        }
+
+        assert_eq!(verifier.last_counter(), Some(400));
+    }
+
+    #[test]
+    fn test_verify_hotp_replayed() {
+        use super::*;
+        let secret = String::from("abcdef234567");
+        let code = match hotp::generate_6_digit_hotp_string(&400u64, &secret) {
+            Ok(s) => s,
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
+        };
+        let mut verifier = Verifier::from_last_counter(&400u64);
+
+        match verifier.verify_hotp(&400u64, &secret, &3u64, &code, &Digits::Six, None) {
+            Ok(v) => assert_eq!(v, Outcome::Replayed),
+            Err(e) => panic!("An error occurred when verifying the hotp: {}", e),
+        }
+
+        assert_eq!(verifier.last_counter(), Some(400));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_verify_totp_replayed() {
+        use super::*;
+        let secret = String::from("abcdef234567abcdef234567");
+        let code = match totp::generate_6_digit_totp_string(&secret, &30) {
+            Ok(s) => s,
+            Err(e) => panic!("An error occurred when generating the totp: {}", e),
+        };
+        let mut verifier = Verifier::new();
+
+        match verifier.verify_totp(&secret, &1, &code, &30, &Digits::Six, None) {
+            Ok(v) => assert!(v.is_valid()),
+            Err(e) => panic!("An error occurred when verifying the totp: {}", e),
+        }
+
+        match verifier.verify_totp(&secret, &1, &code, &30, &Digits::Six, None) {
+            Ok(v) => assert_eq!(v, Outcome::Replayed),
+            Err(e) => panic!("An error occurred when verifying the totp: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_verify_hotp_throttled() {
+        use super::*;
+        use crate::throttle::FixedLockout;
+        let secret = String::from("abcdef234567");
+        let policy = FixedLockout {
+            max_failures: 2,
+            lockout_secs: 30,
+        };
+        let code = match hotp::generate_6_digit_hotp_string(&10u64, &secret) {
+            Ok(s) => s,
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
+        };
+        let wrong = String::from("000000");
+        let mut verifier = Verifier::new();
+
+        for expected in [
+            Outcome::Invalid,
+            Outcome::Invalid,
+            Outcome::LockedOut { until: 1030 },
+        ] {
+            match verifier.verify_hotp_throttled(
+                &policy,
+                &1000,
+                &10u64,
+                &secret,
+                &0u64,
+                &wrong,
+                &Digits::Six,
+                None,
+            ) {
+                Ok(v) => assert_eq!(v, expected),
+                Err(e) => panic!("An error occurred when verifying the hotp: {}", e),
+            }
+        }
+
+        // The correct code is refused until the lockout expires.
+        match verifier.verify_hotp_throttled(
+            &policy,
+            &1029,
+            &10u64,
+            &secret,
+            &0u64,
+            &code,
+            &Digits::Six,
+            None,
+        ) {
+            Ok(v) => assert_eq!(v, Outcome::LockedOut { until: 1030 }),
+            Err(e) => panic!("An error occurred when verifying the hotp: {}", e),
+        }
+
+        match verifier.verify_hotp_throttled(
+            &policy,
+            &1030,
+            &10u64,
+            &secret,
+            &0u64,
+            &code,
+            &Digits::Six,
+            None,
+        ) {
+            Ok(v) => assert_eq!(
+                v,
+                Outcome::Valid {
+                    counter: 10,
+                    step_delta: 0,
+                }
+            ),
+            Err(e) => panic!("An error occurred when verifying the hotp: {}", e),
+        }
+
+        assert_eq!(verifier.attempts().failures(), 0);
+    }
+
+    #[test]
+    fn test_verify_totp_drift() {
+        use super::*;
+        use crate::throttle::FixedLockout;
+        let secret = String::from("abcdef234567abcdef234567");
+        let policy = FixedLockout {
+            max_failures: 5,
+            lockout_secs: 30,
+        };
+        let mut verifier = Verifier::new();
+
+        // The device runs two time steps ahead of the server.
+        for step in [100u64, 101, 102] {
+            let code = match hotp::generate_6_digit_hotp_string(&(step + 2), &secret) {
+                Ok(s) => s,
+                Err(e) => panic!("An error occurred when generating the hotp: {}", e),
+            };
+            match verifier.verify_totp_throttled(
+                &policy,
+                &(step * 30),
+                &secret,
+                &2,
+                &code,
+                &30,
+                &Digits::Six,
+                None,
+            ) {
+                Ok(v) => assert_eq!(
+                    v,
+                    Outcome::Valid {
+                        counter: step + 2,
+                        step_delta: 2,
+                    }
+                ),
+                Err(e) => panic!("An error occurred when verifying the totp: {}", e),
+            }
+        }
+
+        assert_eq!(verifier.drift().centre(), 2);
+
+        // With the window centred on the drift, a code three steps ahead is accepted with an
+        // offset of one.
+        let code = match hotp::generate_6_digit_hotp_string(&206, &secret) {
+            Ok(s) => s,
+            Err(e) => panic!("An error occurred when generating the hotp: {}", e),
+        };
+        match verifier.verify_totp_throttled(
+            &policy,
+            &(203 * 30),
+            &secret,
+            &1,
+            &code,
+            &30,
+            &Digits::Six,
+            None,
+        ) {
+            Ok(v) => assert_eq!(
+                v,
+                Outcome::Valid {
+                    counter: 206,
+                    step_delta: 3,
+                }
+            ),
+            Err(e) => panic!("An error occurred when verifying the totp: {}", e),
+        }
+    }
+}